@@ -1,17 +1,99 @@
-use cgmath::Deg;
+use cgmath::{Angle, Deg, Matrix4, Vector4};
 
+use crate::renderer::DepthMode;
 
+
+/// How a camera projects the scene onto the screen.
+#[derive(Clone, Debug)]
+pub enum Projection {
+    /// Symmetric perspective projection. `fov` is the vertical field of view; the horizontal one follows the aspect ratio.
+    Perspective { fov: Deg<f32> },
+    /// Off-axis perspective projection, e.g. for portals or head-tracked displays. The extents are the
+    /// edges of the view at a distance of 1 unit, so `top = tan(fov / 2)` for a symmetric frustum.
+    Frustum { left: f32, right: f32, bottom: f32, top: f32 },
+    /// Orthographic projection showing `height` world units vertically; the width follows the aspect ratio.
+    Orthographic { height: f32 },
+    /// User-supplied projection matrix, already in Vulkan clip space (y down, depth from 0 to 1, or
+    /// from 1 to 0 with `DepthMode::ReverseZ`). `Camera::near` and `Camera::far` are ignored.
+    Custom(Matrix4<f32>),
+}
+
+
+#[derive(Clone, Debug)]
 pub struct Camera {
-    /// Field of fiew. Note that this is the horizontal half-angle, i.e. fov = 45 means a 90 degree horizontal FOV.
-    pub fov: Deg<f32>
+    pub projection: Projection,
+    /// Distance to the near clipping plane.
+    pub near: f32,
+    /// Distance to the far clipping plane. `std::f32::INFINITY` for an infinite far plane, which is
+    /// only supported by perspective projections. Perspective projections always use an infinite far
+    /// plane with `DepthMode::ReverseZ`.
+    pub far: f32,
 }
 
 
 impl Camera {
     /// Creates a new Camera.
     pub fn new() -> Camera {
+        Camera::perspective(Deg(45.0))
+    }
+
+
+    /// Creates a perspective camera with a vertical field of view.
+    pub fn perspective(fov: Deg<f32>) -> Camera {
         Camera {
-            fov: Deg(45.0) // 90 degrees
+            projection: Projection::Perspective { fov },
+            near: 0.1,
+            far: 100.0,
         }
     }
-}
\ No newline at end of file
+
+
+    /// Creates an orthographic camera showing `height` world units vertically.
+    pub fn orthographic(height: f32) -> Camera {
+        Camera {
+            projection: Projection::Orthographic { height },
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+
+
+    /// Projection matrix in Vulkan clip space, for a viewport with the given aspect ratio (width / height).
+    pub fn projection_matrix(&self, aspect: f32, depth_mode: DepthMode) -> Matrix4<f32> {
+        let clip = depth_mode.clip_correction();
+        let near = self.near;
+        let far = match depth_mode {
+            DepthMode::Standard => self.far,
+            DepthMode::ReverseZ => std::f32::INFINITY,
+        };
+        match self.projection {
+            Projection::Perspective { fov } => {
+                let top = (fov / 2.0).tan();
+                clip * frustum(-top * aspect, top * aspect, -top, top, near, far)
+            },
+            Projection::Frustum { left, right, bottom, top } => {
+                clip * frustum(left, right, bottom, top, near, far)
+            },
+            Projection::Orthographic { height } => {
+                let half = [height * aspect / 2.0, height / 2.0];
+                clip * cgmath::ortho(-half[0], half[0], -half[1], half[1], near, self.far)
+            },
+            Projection::Custom(matrix) => matrix,
+        }
+    }
+}
+
+
+/// OpenGL style perspective frustum, with edges given at a distance of 1. Supports an infinite `far`.
+fn frustum(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Matrix4<f32> {
+    if far.is_finite() {
+        return cgmath::frustum(left * near, right * near, bottom * near, top * near, near, far);
+    }
+    // limit of cgmath::frustum as far goes to infinity
+    Matrix4::from_cols(
+        Vector4::new(2.0 / (right - left), 0.0, 0.0, 0.0),
+        Vector4::new(0.0, 2.0 / (top - bottom), 0.0, 0.0),
+        Vector4::new((right + left) / (right - left), (top + bottom) / (top - bottom), -1.0, -1.0),
+        Vector4::new(0.0, 0.0, -2.0 * near, 0.0),
+    )
+}
@@ -5,30 +5,56 @@ use vulkano::pipeline::{ComputePipeline, ComputePipelineAbstract};
 use vulkano::descriptor::DescriptorSet;
 use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
-use vulkano::device::{Device, Queue};
-use vulkano::sync::GpuFuture;
-use std::sync::atomic::{AtomicBool, Ordering};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, D32Sfloat, R32Sfloat};
+use vulkano::sampler::{Sampler, Filter, SamplerAddressMode, MipmapMode};
+use vulkano::image::{AttachmentImage, ImageUsage};
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector4};
 
+use crate::readback::ReadbackRing;
+use crate::renderer::{RenderInfo, DepthMode};
 
-lazy_static! {
-    pub static ref HISTOGRAM_COMPUTE_WORKING: AtomicBool = AtomicBool::new(false);
+
+const HISTOGRAM_BIN_GROUPS: u32 = 64;
+
+
+/// Output of the histogram reduce pass. Layout matches `Result` in `histogram_reduce.comp`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct HistogramResult {
+    pub bins: [u32; 128],
+    pub low_percentile_bin: f32,
+    pub high_percentile_bin: f32,
+    pub total: u32,
 }
 
 
+/// Luminance histogram, binned and reduced to percentiles entirely on the GPU.
+///
+/// Reads `attachments.luma_render` directly at full resolution, weighted by the metering mode.
+/// Recorded into the post process command buffer, and read back through a `ReadbackRing`.
 pub struct HistogramCompute {
-    pub pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
-    pub source_buffer: Arc<CpuAccessibleBufferXalloc<[u32]>>,
-    pub bins_buffer: Arc<CpuAccessibleBufferXalloc<[u32]>>,
-    pub desc_set: Arc<dyn DescriptorSet + Send + Sync>,
+    bin_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    reduce_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    bins_buffer: Arc<CpuAccessibleBufferXalloc<[u32]>>,
+    sampler: Arc<Sampler>,
+    /// Result buffers, and the reduce pass descriptor sets writing them.
+    results: ReadbackRing<(Arc<CpuAccessibleBufferXalloc<HistogramResult>>, Arc<dyn DescriptorSet + Send + Sync>), ()>,
     pub bins: [u32; 128],
     pub low_percentile_bin: f32,
     pub high_percentile_bin: f32,
+    /// Set when `poll` reads back a usable result, cleared by the renderer once it's been metered.
+    pub new_result: bool,
 }
 
 impl HistogramCompute {
     pub fn new(device: Arc<Device>) -> Self {
-        let pipeline = Arc::new({
-            let shader = crate::shader::histogram::Shader::load(device.clone()).unwrap();
+        let bin_pipeline = Arc::new({
+            let shader = crate::shader::histogram::bin::Shader::load(device.clone()).unwrap();
+            ComputePipeline::new(device.clone(), &shader.main_entry_point(), &()).unwrap()
+        });
+        let reduce_pipeline = Arc::new({
+            let shader = crate::shader::histogram::reduce::Shader::load(device.clone()).unwrap();
             ComputePipeline::new(device.clone(), &shader.main_entry_point(), &()).unwrap()
         });
 
@@ -39,78 +65,349 @@ impl HistogramCompute {
             ..BufferUsage::none()
         };
 
-        let source_buffer = CpuAccessibleBufferXalloc::from_iter(device.clone(),  storage_buf_usage.clone(), [0u32; 512*512].iter().cloned()).unwrap();
         let bins_buffer = CpuAccessibleBufferXalloc::from_iter(device.clone(), storage_buf_usage.clone(), [0u32; 128].iter().cloned()).unwrap();
 
-        let desc_set = Arc::new(PersistentDescriptorSet::start(pipeline.clone(), 0)
-            .add_buffer(source_buffer.clone()).unwrap()
-            .add_buffer(bins_buffer.clone()).unwrap()
-            .build().unwrap()
-        );
+        let sampler = Sampler::new(device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Nearest,
+                                   SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                   0.0, 1.0, 0.0, 0.0).unwrap();
+
+        let results = ReadbackRing::new(|| {
+            let result = CpuAccessibleBufferXalloc::from_data(device.clone(), storage_buf_usage.clone(), HistogramResult {
+                bins: [0u32; 128],
+                low_percentile_bin: 0.0,
+                high_percentile_bin: 127.0,
+                total: 0
+            }).unwrap();
+            let set: Arc<dyn DescriptorSet + Send + Sync> = Arc::new(PersistentDescriptorSet::start(reduce_pipeline.clone(), 0)
+                .add_buffer(bins_buffer.clone()).unwrap()
+                .add_buffer(result.clone()).unwrap()
+                .build().unwrap()
+            );
+            (result, set)
+        });
 
         Self {
-            pipeline,
-            source_buffer,
+            bin_pipeline,
+            reduce_pipeline,
             bins_buffer,
-            desc_set,
+            sampler,
+            results,
             bins: [0u32; 128],
             low_percentile_bin: 0.0,
             high_percentile_bin: 127.0,
+            new_result: false,
         }
     }
 
-    // blocks until execution is finished, so call on another thread
-    pub fn submit(&mut self, device: Arc<Device>, queue: Arc<Queue>) {
-        HISTOGRAM_COMPUTE_WORKING.store(true, Ordering::Relaxed);
-        {
-            let mut lock = self.bins_buffer.write().unwrap();
-            for b in lock.iter_mut() {
-                *b = 0;
-            }
+
+    /// Records binning and reduction of the luminance attachment into `cb`. Must be recorded after
+    /// the post process pass has written it for this frame.
+    pub fn record(&mut self, cb: AutoCommandBufferBuilder, info: &RenderInfo) -> AutoCommandBufferBuilder {
+        let reduce_desc_set = match self.results.begin(info.frame_index, ()) {
+            Some((_, set)) => set.clone(),
+            // every slot is still in flight, skip this frame rather than stall
+            None => return cb
+        };
+
+        let bin_desc_set = Arc::new(PersistentDescriptorSet::start(self.bin_pipeline.clone(), 0)
+            .add_sampled_image(info.attachments.luma_render.clone(), self.sampler.clone()).unwrap()
+            .add_buffer(self.bins_buffer.clone()).unwrap()
+            .build().unwrap()
+        );
+
+        let cb = cb.fill_buffer(self.bins_buffer.clone(), 0).unwrap()
+            .dispatch([HISTOGRAM_BIN_GROUPS, 1, 1], self.bin_pipeline.clone(), bin_desc_set,
+                      crate::shader::histogram::bin::ty::Constants {
+                          dimensions: info.dimensions,
+                          metering_mode: info.tonemapping_info.metering_mode as u32,
+                          metering_radius: info.tonemapping_info.metering_radius,
+                      }).unwrap()
+            .dispatch([1, 1, 1], self.reduce_pipeline.clone(), reduce_desc_set,
+                      crate::shader::histogram::reduce::ty::Constants {
+                          low_percentile: info.tonemapping_info.low_percentile,
+                          high_percentile: info.tonemapping_info.high_percentile,
+                      }).unwrap();
+        cb
+    }
+
+
+    /// Reads back any results the GPU has finished with, without blocking. Keeps the newest one.
+    pub fn poll(&mut self) {
+        let result = match self.results.poll(|(result, _), _| result.read().ok().map(|lock| *lock)) {
+            Some((_, _, result)) => result,
+            None => return
+        };
+        self.bins = result.bins;
+        if result.total == 0 {
+            // nothing was counted (e.g. all black), don't meter garbage
+            return;
         }
-        let cb = AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family()).unwrap()
-            .dispatch([16, 1, 1], self.pipeline.clone(), self.desc_set.clone(), ()).unwrap()
-            .build().unwrap();
-        let future = vulkano::sync::now(device.clone()).then_execute(queue.clone(), cb);
-        match future {
-            Err(e) => {
-                println!("Error in histogram compute: {}", e);
-                HISTOGRAM_COMPUTE_WORKING.store(false, Ordering::Relaxed);
-                return;
+        self.low_percentile_bin = result.low_percentile_bin;
+        self.high_percentile_bin = result.high_percentile_bin;
+        self.new_result = true;
+    }
+}
+
+
+/// Screen-space ambient occlusion. Reads the position and normal G-buffers and writes
+/// `attachments.ao`, then blurs it in place with a separable bilateral blur.
+///
+/// Recorded into the deferred lighting command buffer, before the lighting render pass.
+pub struct AmbientOcclusionCompute {
+    pub ao_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    pub blur_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+}
+
+impl AmbientOcclusionCompute {
+    pub fn new(device: Arc<Device>) -> Self {
+        let ao_pipeline = Arc::new({
+            let shader = crate::shader::ssao::main::Shader::load(device.clone()).unwrap();
+            ComputePipeline::new(device.clone(), &shader.main_entry_point(), &()).unwrap()
+        });
+        let blur_pipeline = Arc::new({
+            let shader = crate::shader::ssao::blur::Shader::load(device.clone()).unwrap();
+            ComputePipeline::new(device.clone(), &shader.main_entry_point(), &()).unwrap()
+        });
+
+        let sampler = Sampler::new(device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Nearest,
+                                   SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                   0.0, 1.0, 0.0, 0.0).unwrap();
+
+        Self {
+            ao_pipeline,
+            blur_pipeline,
+            sampler,
+        }
+    }
+
+    /// Records the AO and blur dispatches into `cb`. If AO is disabled, the AO buffer is cleared to 1.0 instead.
+    pub fn record(&self, cb: AutoCommandBufferBuilder, info: &RenderInfo) -> AutoCommandBufferBuilder {
+        let attachments = &info.attachments;
+        if !info.ao_info.enabled {
+            return cb.clear_color_image(attachments.ao.clone(), ClearValue::Float([1.0, 1.0, 1.0, 1.0])).unwrap();
+        }
+
+        let groups = [(info.dimensions[0] + 7) / 8, (info.dimensions[1] + 7) / 8, 1];
+
+        let ao_set = Arc::new(PersistentDescriptorSet::start(self.ao_pipeline.clone(), 0)
+            .add_sampled_image(attachments.position.clone(), self.sampler.clone()).unwrap()
+            .add_sampled_image(attachments.normal.clone(), self.sampler.clone()).unwrap()
+            .add_image(attachments.ao.clone()).unwrap()
+            .build().unwrap()
+        );
+        let blur_h_set = Arc::new(PersistentDescriptorSet::start(self.blur_pipeline.clone(), 0)
+            .add_sampled_image(attachments.ao.clone(), self.sampler.clone()).unwrap()
+            .add_sampled_image(attachments.position.clone(), self.sampler.clone()).unwrap()
+            .add_image(attachments.ao_scratch.clone()).unwrap()
+            .build().unwrap()
+        );
+        let blur_v_set = Arc::new(PersistentDescriptorSet::start(self.blur_pipeline.clone(), 0)
+            .add_sampled_image(attachments.ao_scratch.clone(), self.sampler.clone()).unwrap()
+            .add_sampled_image(attachments.position.clone(), self.sampler.clone()).unwrap()
+            .add_image(attachments.ao.clone()).unwrap()
+            .build().unwrap()
+        );
+
+        cb.dispatch(groups, self.ao_pipeline.clone(), ao_set, crate::shader::ssao::main::ty::Constants {
+            view: info.view_mat.into(),
+            proj_scale: [info.proj_mat.x.x, info.proj_mat.y.y.abs()],
+            proj_w: [info.proj_mat.z.w, info.proj_mat.w.w],
+            radius: info.ao_info.radius,
+            intensity: info.ao_info.intensity,
+            bias: info.ao_info.bias,
+            frame_index: info.frame_index as u32,
+        }).unwrap()
+          .dispatch(groups, self.blur_pipeline.clone(), blur_h_set, crate::shader::ssao::blur::ty::Constants {
+            direction: [1, 0],
+            sharpness: info.ao_info.blur_sharpness,
+        }).unwrap()
+          .dispatch(groups, self.blur_pipeline.clone(), blur_v_set, crate::shader::ssao::blur::ty::Constants {
+            direction: [0, 1],
+            sharpness: info.ao_info.blur_sharpness,
+        }).unwrap()
+    }
+}
+
+
+/// One level of a Hi-Z pyramid, read back to the CPU.
+struct HiZLevel {
+    dimensions: [u32; 2],
+    depth: Vec<f32>,
+}
+
+
+/// Hierarchical-Z pyramid of the occlusion pass depth buffer, for occlusion culling meshes on the CPU.
+///
+/// Each level keeps the farthest depth of the 2x2 texels below it, down to 1x1. Built on the GPU
+/// right after the occlusion pass and read back through a `ReadbackRing`. Boxes are tested against the
+/// newest pyramid read back, with the view-projection it was rendered with.
+pub struct HiZCompute {
+    pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    levels: Vec<Arc<AttachmentImage<R32Sfloat>>>,
+    /// Readback buffers of each level, with the view-projection the pyramid was rendered with.
+    readback: ReadbackRing<Vec<Arc<CpuAccessibleBufferXalloc<[f32]>>>, Matrix4<f32>>,
+    pyramid: Vec<HiZLevel>,
+    view_proj: Matrix4<f32>,
+    depth_mode: DepthMode,
+}
+
+impl HiZCompute {
+    /// Creates a pyramid for a depth buffer of the given size.
+    pub fn new(device: Arc<Device>, dimensions: [u32; 2], depth_mode: DepthMode) -> Self {
+        let pipeline = Arc::new({
+            let shader = crate::shader::hiz::downsample::Shader::load(device.clone()).unwrap();
+            ComputePipeline::new(device.clone(), &shader.main_entry_point(), &()).unwrap()
+        });
+
+        let sampler = Sampler::new(device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Nearest,
+                                   SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                   0.0, 1.0, 0.0, 0.0).unwrap();
+
+        let usage = ImageUsage {
+            storage: true,
+            sampled: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+        let mut level_dimensions = vec![dimensions];
+        while *level_dimensions.last().unwrap() != [1, 1] {
+            let last = level_dimensions.last().unwrap();
+            level_dimensions.push([(last[0] / 2).max(1), (last[1] / 2).max(1)]);
+        }
+        let levels = level_dimensions.iter()
+            .map(|d| AttachmentImage::with_usage(device.clone(), *d, R32Sfloat, usage).unwrap())
+            .collect();
+        let readback = ReadbackRing::new(|| {
+            level_dimensions.iter().map(|d| {
+                CpuAccessibleBufferXalloc::from_iter(device.clone(), BufferUsage::transfer_destination(),
+                                                     vec![1f32; (d[0] * d[1]) as usize].iter().cloned()).unwrap()
+            }).collect()
+        });
+
+        Self {
+            pipeline,
+            sampler,
+            levels,
+            readback,
+            pyramid: Vec::new(),
+            view_proj: Matrix4::identity(),
+            depth_mode,
+        }
+    }
+
+    /// Records building the pyramid from `depth`, and copying it into the next ring slot.
+    pub fn record(&mut self, cb: AutoCommandBufferBuilder, depth: Arc<AttachmentImage<D32Sfloat>>,
+                  view_proj: Matrix4<f32>, frame_index: u64) -> AutoCommandBufferBuilder {
+        let buffers = match self.readback.begin(frame_index, view_proj) {
+            Some(buffers) => buffers.clone(),
+            // every slot is still in flight, skip this frame rather than stall
+            None => return cb
+        };
+
+        let first_set = Arc::new(PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+            .add_sampled_image(depth, self.sampler.clone()).unwrap()
+            .add_image(self.levels[0].clone()).unwrap()
+            .build().unwrap()
+        );
+        let constants = crate::shader::hiz::downsample::ty::Constants {
+            reverse_z: (self.depth_mode == DepthMode::ReverseZ) as u32,
+        };
+        let mut cb = cb.dispatch(hiz_groups(self.levels[0].dimensions()), self.pipeline.clone(), first_set, constants).unwrap();
+        for i in 1..self.levels.len() {
+            let set = Arc::new(PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                .add_sampled_image(self.levels[i - 1].clone(), self.sampler.clone()).unwrap()
+                .add_image(self.levels[i].clone()).unwrap()
+                .build().unwrap()
+            );
+            cb = cb.dispatch(hiz_groups(self.levels[i].dimensions()), self.pipeline.clone(), set, constants).unwrap();
+        }
+        for (level, buffer) in self.levels.iter().zip(buffers.into_iter()) {
+            cb = cb.copy_image_to_buffer(level.clone(), buffer).unwrap();
+        }
+        cb
+    }
+
+    /// Reads back any finished pyramids, keeping the newest one.
+    pub fn poll(&mut self) {
+        let levels = &self.levels;
+        let newest = self.readback.poll(|buffers, _| {
+            let mut pyramid = Vec::with_capacity(levels.len());
+            for (level, buffer) in levels.iter().zip(buffers.iter()) {
+                pyramid.push(HiZLevel { dimensions: level.dimensions(), depth: buffer.read().ok()?.to_vec() });
             }
-            _ => {}
+            Some(pyramid)
+        });
+        if let Some((_, view_proj, pyramid)) = newest {
+            self.pyramid = pyramid;
+            self.view_proj = view_proj;
+        }
+    }
+
+    /// True if a box is completely hidden behind the depth in the pyramid.
+    ///
+    /// `min` and `max` are corners of an object space box, transformed by `world`. Boxes that cross
+    /// the near plane or the edge of the occlusion frame are never reported as occluded.
+    pub fn is_occluded(&self, min: Point3<f32>, max: Point3<f32>, world: &Matrix4<f32>) -> bool {
+        if self.pyramid.is_empty() {
+            return false;
         }
-        let future = future.unwrap().then_signal_fence_and_flush().unwrap();
-        future.wait(None).unwrap();
-        {
-            let lock = self.bins_buffer.read().unwrap();
-            let mut counted = 0;
-            let mut low_found = false;
-            let mut high_found = false;
-            for (i, b) in lock.iter().enumerate() {
-                self.bins[i] = *b;
-                counted += *b;
-                if !low_found && counted >= 157286 { // 60%
-                    // find how far through the bin the threshold is
-                    let bin_begin = counted - *b;
-                    let overshoot = 157286 - bin_begin;
-                    let depth = overshoot as f32 / *b as f32;
-                    // store value as (decimal) number of bins
-                    self.low_percentile_bin = i as f32 + depth;
-                    low_found = true;
-                }
-                if !high_found && counted >= 235930 { // 90%
-                    // find how far through the bin the threshold is
-                    let bin_begin = counted - *b;
-                    let overshoot = 235930 - bin_begin;
-                    let depth = overshoot as f32 / *b as f32;
-                    // store value as (decimal) number of bins
-                    self.high_percentile_bin = i as f32 + depth;
-                    high_found = true;
-                }
+
+        let world_view_proj = self.view_proj * world;
+        let mut lo = [std::f32::MAX; 2];
+        let mut hi = [std::f32::MIN; 2];
+        // depths are compared as standard depth, 0 at the near plane
+        let standard = |depth: f32| match self.depth_mode {
+            DepthMode::Standard => depth,
+            DepthMode::ReverseZ => 1.0 - depth,
+        };
+        let mut nearest = std::f32::MAX;
+        for i in 0..8 {
+            let corner = Vector4::new(if i & 1 == 0 { min.x } else { max.x },
+                                      if i & 2 == 0 { min.y } else { max.y },
+                                      if i & 4 == 0 { min.z } else { max.z },
+                                      1.0);
+            let clip = world_view_proj * corner;
+            if clip.w <= 0.0 {
+                return false;
             }
+            let ndc = clip.truncate() / clip.w;
+            lo = [lo[0].min(ndc.x), lo[1].min(ndc.y)];
+            hi = [hi[0].max(ndc.x), hi[1].max(ndc.y)];
+            nearest = nearest.min(standard(ndc.z));
+        }
+        if nearest <= 0.0 || lo[0] < -1.0 || lo[1] < -1.0 || hi[0] > 1.0 || hi[1] > 1.0 {
+            return false;
         }
 
-        HISTOGRAM_COMPUTE_WORKING.store(false, Ordering::Relaxed);
+        // screen rect in level 0 texels, then the level where it covers about 2x2 texels
+        let base = self.pyramid[0].dimensions;
+        let x0 = (lo[0] * 0.5 + 0.5) * base[0] as f32;
+        let x1 = (hi[0] * 0.5 + 0.5) * base[0] as f32;
+        let y0 = (lo[1] * 0.5 + 0.5) * base[1] as f32;
+        let y1 = (hi[1] * 0.5 + 0.5) * base[1] as f32;
+        let extent = (x1 - x0).max(y1 - y0).max(1.0);
+        let level = &self.pyramid[(extent.log2().ceil() as usize).min(self.pyramid.len() - 1)];
+
+        let scale = [level.dimensions[0] as f32 / base[0] as f32, level.dimensions[1] as f32 / base[1] as f32];
+        let tx0 = ((x0 * scale[0]) as u32).min(level.dimensions[0] - 1);
+        let tx1 = ((x1 * scale[0]) as u32).min(level.dimensions[0] - 1);
+        let ty0 = ((y0 * scale[1]) as u32).min(level.dimensions[1] - 1);
+        let ty1 = ((y1 * scale[1]) as u32).min(level.dimensions[1] - 1);
+
+        let mut farthest = 0f32;
+        for y in ty0..=ty1 {
+            for x in tx0..=tx1 {
+                farthest = farthest.max(standard(level.depth[(y * level.dimensions[0] + x) as usize]));
+            }
+        }
+        nearest > farthest
     }
 }
+
+
+/// Workgroups to cover an image with `hiz.comp`'s 8x8 groups.
+fn hiz_groups(dimensions: [u32; 2]) -> [u32; 3] {
+    [(dimensions[0] + 7) / 8, (dimensions[1] + 7) / 8, 1]
+}
@@ -0,0 +1,287 @@
+//! Immediate-mode debug drawing.
+//!
+//! Shapes are queued from anywhere with access to the render queues, and are drawn by
+//! `LinesRenderPipeline` (and `TextRenderPipeline` for `text3d`) for the given duration.
+//!
+//! ```ignore
+//! let mut queues = renderer.info.render_queues.write().unwrap();
+//! queues.debug.aabb(min, max, [1.0, 0.0, 0.0, 1.0], DebugDrawOptions::default());
+//! queues.debug.arrow(origin, origin + velocity, [0.0, 1.0, 0.0, 1.0], DebugDrawOptions::for_seconds(2.0));
+//! ```
+
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3, Vector4};
+
+use crate::geometry::VertexPositionColorWidth;
+use crate::pipeline::text::{TextData, TextPlacement};
+use crate::renderer::DepthMode;
+
+
+/// Number of line segments in each circle of a debug sphere.
+const SPHERE_SEGMENTS: usize = 32;
+
+
+/// Per-call options for debug shapes.
+#[derive(Clone, Copy, Debug)]
+pub struct DebugDrawOptions {
+    /// Seconds to keep drawing the shape for. 0.0 draws it for a single frame.
+    pub duration: f32,
+    /// Whether the shape is hidden behind scene geometry.
+    pub depth_test: bool,
+    /// Line width in pixels, 0.0 = `LinesInfo::width`.
+    pub width: f32,
+}
+impl Default for DebugDrawOptions {
+    fn default() -> Self {
+        Self {
+            duration: 0.0,
+            depth_test: true,
+            width: 0.0,
+        }
+    }
+}
+impl DebugDrawOptions {
+    pub fn for_seconds(duration: f32) -> Self {
+        Self { duration, ..Self::default() }
+    }
+
+    /// Options for a shape drawn on top of everything.
+    pub fn no_depth_test() -> Self {
+        Self { depth_test: false, ..Self::default() }
+    }
+}
+
+
+/// A queued shape, as a line list.
+pub struct DebugLines {
+    pub vertices: Vec<VertexPositionColorWidth>,
+    pub depth_test: bool,
+    remaining: f32,
+    added_frame: u64,
+}
+
+
+struct DebugText {
+    text: TextData,
+    remaining: f32,
+    added_frame: u64,
+}
+
+
+/// Queue of debug shapes. Lives in `RenderQueues::debug`.
+#[derive(Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLines>,
+    text: Vec<DebugText>,
+    /// Incremented every frame, so shapes queued since the last frame are always drawn at least once.
+    frame: u64,
+}
+
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+
+    /// Expires shapes that have been drawn for their whole duration. Called by the renderer once per frame.
+    pub fn advance(&mut self, dt: f32) {
+        let frame = self.frame;
+        for l in self.lines.iter_mut().filter(|l| l.added_frame != frame) {
+            l.remaining -= dt;
+        }
+        for t in self.text.iter_mut().filter(|t| t.added_frame != frame) {
+            t.remaining -= dt;
+        }
+        self.lines.retain(|l| l.added_frame == frame || l.remaining > 0.0);
+        self.text.retain(|t| t.added_frame == frame || t.remaining > 0.0);
+        self.frame += 1;
+    }
+
+
+    /// Removes all queued shapes.
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.text.clear();
+    }
+
+
+    /// Shapes to draw this frame.
+    pub fn lines(&self) -> impl Iterator<Item=&DebugLines> {
+        self.lines.iter()
+    }
+
+
+    /// Text to draw this frame.
+    pub fn text(&self) -> impl Iterator<Item=&TextData> {
+        self.text.iter().map(|t| &t.text)
+    }
+
+
+    /// Queues a line list. Every two points are one segment.
+    pub fn segments(&mut self, points: &[Point3<f32>], color: [f32; 4], options: DebugDrawOptions) {
+        let width = options.width;
+        self.line_vertices(points.iter().map(|p| VertexPositionColorWidth { position: [p.x, p.y, p.z], width, color }).collect(),
+                           options);
+    }
+
+
+    /// Queues a line list with per-vertex colors and widths. `options.width` is ignored.
+    pub fn line_vertices(&mut self, vertices: Vec<VertexPositionColorWidth>, options: DebugDrawOptions) {
+        self.lines.push(DebugLines {
+            vertices,
+            depth_test: options.depth_test,
+            remaining: options.duration,
+            added_frame: self.frame,
+        });
+    }
+
+
+    pub fn line(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4], options: DebugDrawOptions) {
+        self.segments(&[from, to], color, options);
+    }
+
+
+    /// Axis-aligned box between two corners.
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 4], options: DebugDrawOptions) {
+        let corners: Vec<Point3<f32>> = (0..8).map(|i| Point3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        )).collect();
+        self.segments(&box_edges(&corners), color, options);
+    }
+
+
+    /// Wireframe sphere, drawn as a circle around each axis.
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 4], options: DebugDrawOptions) {
+        let mut points = Vec::with_capacity(SPHERE_SEGMENTS * 6);
+        let circle = |t: usize| {
+            let a = t as f32 / SPHERE_SEGMENTS as f32 * std::f32::consts::PI * 2.0;
+            (a.cos() * radius, a.sin() * radius)
+        };
+        for i in 0..SPHERE_SEGMENTS {
+            let (x0, y0) = circle(i);
+            let (x1, y1) = circle(i + 1);
+            points.push(center + Vector3::new(x0, y0, 0.0));
+            points.push(center + Vector3::new(x1, y1, 0.0));
+            points.push(center + Vector3::new(x0, 0.0, y0));
+            points.push(center + Vector3::new(x1, 0.0, y1));
+            points.push(center + Vector3::new(0.0, x0, y0));
+            points.push(center + Vector3::new(0.0, x1, y1));
+        }
+        self.segments(&points, color, options);
+    }
+
+
+    /// Line with an arrowhead at `to`.
+    pub fn arrow(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4], options: DebugDrawOptions) {
+        let dir = to - from;
+        let length = dir.magnitude();
+        if length <= 0.0 {
+            return;
+        }
+        let dir = dir / length;
+        // any vector not parallel to dir works for building the head
+        let up = if dir.y.abs() < 0.99 { Vector3::unit_y() } else { Vector3::unit_x() };
+        let side = dir.cross(up).normalize();
+        let up = side.cross(dir);
+        let head = length * 0.2;
+        let base = to - dir * head;
+        let width = head * 0.5;
+        self.segments(&[
+            from, to,
+            to, base + side * width,
+            to, base - side * width,
+            to, base + up * width,
+            to, base - up * width,
+        ], color, options);
+    }
+
+
+    /// Outline of a view frustum, given the inverse of its view-projection matrix and the depth mode it was
+    /// built for. Edges are cut off `max_distance` from the near plane, for infinite far planes.
+    pub fn frustum(&mut self, inv_view_proj: Matrix4<f32>, depth_mode: DepthMode, max_distance: f32, color: [f32; 4], options: DebugDrawOptions) {
+        let unproject = |x: f32, y: f32, depth: f32| inv_view_proj * Vector4::new(x, y, depth, 1.0);
+        let mut corners = vec![Point3::origin(); 8];
+        for i in 0..4 {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let near = unproject(x, y, depth_mode.near_depth());
+            let near = Point3::from_vec(near.truncate() / near.w);
+            let far = unproject(x, y, depth_mode.clear_depth());
+            let far = Point3::from_vec(far.truncate() / far.w);
+            corners[i] = near;
+            corners[i + 4] = if far.x.is_finite() && far.y.is_finite() && far.z.is_finite() && (far - near).magnitude() <= max_distance {
+                far
+            }
+            else {
+                // far plane at infinity (w = 0), take the edge's direction from a depth between the planes
+                let mid = unproject(x, y, (depth_mode.near_depth() + depth_mode.clear_depth()) * 0.5);
+                let mid = Point3::from_vec(mid.truncate() / mid.w);
+                near + (mid - near).normalize() * max_distance
+            };
+        }
+        self.segments(&box_edges(&corners), color, options);
+    }
+
+
+    /// The axes of a transform, colored red (X), green (Y) and blue (Z).
+    pub fn axes(&mut self, transform: Matrix4<f32>, size: f32, options: DebugDrawOptions) {
+        let origin = transform.transform_point(Point3::origin());
+        let axes = [(Vector3::unit_x(), [1.0, 0.0, 0.0, 1.0]),
+                    (Vector3::unit_y(), [0.0, 1.0, 0.0, 1.0]),
+                    (Vector3::unit_z(), [0.0, 0.0, 1.0, 1.0])];
+        for (axis, color) in axes.iter() {
+            let end = transform.transform_point(Point3::from_vec(*axis * size));
+            self.line(origin, end, *color, options);
+        }
+    }
+
+
+    /// Square grid on the XZ plane, centered on `center`, with `cells` cells along each side.
+    pub fn grid(&mut self, center: Point3<f32>, cell_size: f32, cells: u32, color: [f32; 4], options: DebugDrawOptions) {
+        let half = cells as f32 * cell_size / 2.0;
+        let mut points = Vec::with_capacity((cells as usize + 1) * 4);
+        for i in 0..=cells {
+            let offset = i as f32 * cell_size - half;
+            points.push(center + Vector3::new(offset, 0.0, -half));
+            points.push(center + Vector3::new(offset, 0.0, half));
+            points.push(center + Vector3::new(-half, 0.0, offset));
+            points.push(center + Vector3::new(half, 0.0, offset));
+        }
+        self.segments(&points, color, options);
+    }
+
+
+    /// Text at a point in world space, facing the camera with a constant on-screen size.
+    pub fn text3d(&mut self, position: Point3<f32>, text: &str, color: [f32; 4], options: DebugDrawOptions) {
+        self.text.push(DebugText {
+            text: TextData {
+                text: text.to_string(),
+                color,
+                size: 14.0,
+                placement: TextPlacement::ScreenSizeBillboard { position },
+                depth_test: options.depth_test,
+                ..TextData::default()
+            },
+            remaining: options.duration,
+            added_frame: self.frame,
+        });
+    }
+}
+
+
+/// Edges of a box as a line list, from corners indexed by bits x = 1, y = 2, z = 4.
+fn box_edges(corners: &[Point3<f32>]) -> Vec<Point3<f32>> {
+    let mut points = Vec::with_capacity(24);
+    for i in 0..8 {
+        for bit in [1, 2, 4].iter() {
+            if i & bit == 0 {
+                points.push(corners[i]);
+                points.push(corners[i | bit]);
+            }
+        }
+    }
+    points
+}
@@ -16,18 +16,32 @@ use toolbox::Transform;
 #[derive(Debug)]
 pub struct Mesh {
     pub transform: Transform,
+    /// Transform from the previous frame, used to generate motion vectors. Rolled forward to `transform`
+    /// by [end_frame](Mesh::end_frame), which should be called once per frame.
+    pub prev_transform: Transform,
     pub vertex_groups: Vec<Arc<VertexGroup<DeferredShadingVertex>>>,
-    pub materials: Vec<Material>
+    pub materials: Vec<Material>,
+    /// ID reported by [Renderer::pick](::renderer::Renderer::pick). 0 if the mesh can't be picked.
+    pub object_id: u32,
 }
 
 
 impl Mesh {
     /// Creates a new mesh with an identity transform and no geometry or materials.
     pub fn new() -> Mesh {
+        Mesh::with_transform(Transform::identity())
+    }
+
+
+    /// Creates a new mesh with no geometry or materials. `prev_transform` starts out the same, so the mesh
+    /// has no motion vectors on its first frame.
+    pub fn with_transform(transform: Transform) -> Mesh {
         Mesh {
-            transform: Transform::identity(),
+            prev_transform: transform.clone(),
+            transform,
             vertex_groups: Vec::new(),
             materials: Vec::new(),
+            object_id: 0,
         }
     }
 
@@ -42,9 +56,19 @@ impl Mesh {
             result.push(MeshRenderQueueEntry {
                 vertex_group: vg.clone(),
                 material: self.materials[vg.material_id as usize].clone(),
-                transform: self.transform.to_matrix()
+                transform: self.transform.to_matrix(),
+                prev_transform: self.prev_transform.to_matrix(),
+                object_id: self.object_id,
+                bounds: vg.bounds,
             });
         }
         result
     }
+
+
+    /// Sets `prev_transform` to the current transform. Call once per frame after queueing the mesh, so a
+    /// mesh that stops moving stops generating motion vectors.
+    pub fn end_frame(&mut self) {
+        self.prev_transform = self.transform.clone();
+    }
 }
\ No newline at end of file
@@ -9,7 +9,7 @@ pub mod vertex;
 pub mod vertexgroup;
 
 pub use self::mesh::Mesh;
-pub use self::vertex::{VertexPositionColorAlpha, VertexPosition, DeferredShadingVertex, VertexPositionObjectId, VertexPositionUV};
+pub use self::vertex::{VertexPositionColorAlpha, VertexPositionColorWidth, VertexPosition, DeferredShadingVertex, VertexPositionObjectId, VertexPositionUV};
 pub use self::vertexgroup::VertexGroup;
 
 
@@ -17,6 +17,7 @@ pub use self::vertexgroup::VertexGroup;
 #[derive(Clone, Debug)]
 pub struct Material {
     /// Name of albedo map, used to look up texture in the [TextureRegistry](::registry::TextureRegistry).
+    /// Can also be the name of a [RenderView](::view::RenderView), to show what it sees.
     pub albedo_map_name: String,
     /// Exponent used in specular lighting calculation. Higher values have sharper highlights.
     pub specular_exponent: f32,
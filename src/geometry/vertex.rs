@@ -42,6 +42,20 @@ pub struct VertexPositionColorAlpha {
 impl_vertex!(VertexPositionColorAlpha, position, color);
 
 
+/// A vertex type with position, line width in pixels, and color + alpha data.
+///
+/// Used for wide lines in LinesRenderPipeline. Also read from a storage buffer, so the layout
+/// must match `LineVertex` in lines_wide.vert.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VertexPositionColorWidth {
+    pub position: [f32; 3],
+    pub width:    f32,
+    pub color:    [f32; 4]
+}
+impl_vertex!(VertexPositionColorWidth, position, width, color);
+
+
 /// A vertex type with position, uv, and color data.
 ///
 /// Used in TextRenderPipeline
@@ -4,10 +4,12 @@
 
 use std::sync::Arc;
 
+use cgmath::Point3;
 use vulkano::buffer::BufferUsage;
 use vulkano::device::Device;
 
 use crate::buffer::CpuAccessibleBufferXalloc;
+use crate::geometry::DeferredShadingVertex;
 
 
 /// Vertex group object. Material id is a `u8` which corresponds to the index of a material in the owning [Mesh](super::Mesh).
@@ -20,6 +22,8 @@ pub struct VertexGroup<V> {
     /// Index buffer. Cpu-accessible, managed by [AutoMemoryPool](::memory::pool::AutoMemoryPool).
     pub index_buffer: Arc<CpuAccessibleBufferXalloc<[u32]>>,
     pub material_id: u8,
+    /// Bounding box of the vertices, used for occlusion culling. Set with [compute_bounds](VertexGroup::compute_bounds).
+    pub bounds: Option<(Point3<f32>, Point3<f32>)>,
 }
 
 
@@ -30,7 +34,26 @@ impl<V> VertexGroup<V> {
         VertexGroup {
             vertex_buffer: CpuAccessibleBufferXalloc::from_iter(device.clone(), BufferUsage::all(), verts).expect("failed to create vertex buffer"),
             index_buffer: CpuAccessibleBufferXalloc::from_iter(device.clone(), BufferUsage::all(), idxs).expect("failed to create index buffer"),
-            material_id
+            material_id,
+            bounds: None,
         }
     }
+}
+
+
+impl VertexGroup<DeferredShadingVertex> {
+    /// Computes `bounds` from the vertex buffer.
+    pub fn compute_bounds(mut self) -> Self {
+        let mut bounds: Option<(Point3<f32>, Point3<f32>)> = None;
+        for v in self.vertex_buffer.read().expect("failed to read vertex buffer").iter() {
+            let p = Point3::new(v.position[0], v.position[1], v.position[2]);
+            bounds = Some(match bounds {
+                Some((min, max)) => (Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                                     Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z))),
+                None => (p, p)
+            });
+        }
+        self.bounds = bounds;
+        self
+    }
 }
\ No newline at end of file
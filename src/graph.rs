@@ -0,0 +1,646 @@
+//! Render graph.
+//!
+//! Passes declare the attachments they read and write, and run in the order they were added. The graph
+//! infers which earlier passes each one depends on from those declarations, so passes on the offscreen and
+//! compute queues are only waited on by the passes that use their results. Built-in attachments are named
+//! after the fields of [RendererAttachments](::renderer::RendererAttachments); passes can also have the
+//! graph allocate attachments for them, which share images with other graph attachments whose lifetimes
+//! don't overlap.
+//!
+//! ```ignore
+//! let desc = PassDesc::new("outline")
+//!     .reads(&["normal", "main_depth"])
+//!     .creates("outline_mask", AttachmentDesc::new(Format::R8Unorm, 1.0))
+//!     .writes(&[SWAPCHAIN]);
+//! renderer.graph_mut().add_pass_after(LINES_PASS, desc, Box::new(OutlinePipeline::new(&renderer.info)))?;
+//! ```
+
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use vulkano::command_buffer::AutoCommandBuffer;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::{AttachmentImage, ImageUsage, ImageViewAccess};
+use vulkano::sync::GpuFuture;
+
+use crate::pipeline::RenderPipelineAbstract;
+use crate::renderer::RenderInfo;
+
+
+pub const OCCLUSION_PASS: &str = "occlusion";
+/// Offscreen and split-screen views, see [RenderView](::view::RenderView).
+pub const VIEWS_PASS: &str = "views";
+/// Only runs on frames where a pick was requested.
+pub const PICKING_PASS: &str = "picking";
+/// Blits split-screen views into the swapchain image. Replaces the passes of the main view while it runs.
+pub const VIEWPORT_COMPOSITE_PASS: &str = "viewport_composite";
+pub const DEFERRED_SHADING_PASS: &str = "deferred_shading";
+pub const DEFERRED_LIGHTING_PASS: &str = "deferred_lighting";
+pub const TEMPORAL_AA_PASS: &str = "temporal_aa";
+pub const POST_PROCESS_PASS: &str = "post_process";
+pub const LINES_PASS: &str = "lines";
+pub const TEXT_PASS: &str = "text";
+
+/// The swapchain image being drawn this frame.
+pub const SWAPCHAIN: &str = "swapchain";
+/// Color targets of all views.
+pub const VIEW_TARGETS: &str = "view_targets";
+
+
+/// Where a custom pass runs in the main view, see
+/// [Renderer::add_custom_pass](../renderer/struct.Renderer.html#method.add_custom_pass).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InsertionPoint {
+    /// After the g-buffer has been filled, before lighting, e.g. for decals. Can modify the g-buffer
+    /// attachments, and read `main_depth`.
+    BeforeLighting,
+    /// After tonemapping and color grading, before lines and text, e.g. for outlines. The swapchain image
+    /// holds the final color and `main_depth` the scene depth.
+    AfterPostProcess,
+    /// On top of everything but imgui, e.g. for HUDs.
+    Overlay,
+}
+impl InsertionPoint {
+    /// Built-in pass that custom passes at this point run after.
+    pub fn anchor(self) -> &'static str {
+        match self {
+            InsertionPoint::BeforeLighting => DEFERRED_SHADING_PASS,
+            InsertionPoint::AfterPostProcess => POST_PROCESS_PASS,
+            InsertionPoint::Overlay => TEXT_PASS,
+        }
+    }
+
+    /// Attachments passes at this point read, in addition to the ones they declare.
+    pub fn reads(self) -> &'static [&'static str] {
+        match self {
+            InsertionPoint::BeforeLighting => &["position", "normal", "albedo", "roughness", "metallic", "velocity", "main_depth"],
+            InsertionPoint::AfterPostProcess => &["scene_color", "main_depth"],
+            InsertionPoint::Overlay => &[],
+        }
+    }
+
+    /// Attachments passes at this point write, in addition to the ones they declare. Passes after
+    /// post-processing draw into the swapchain image, using the default
+    /// [recreate_framebuffers_if_none](../pipeline/trait.RenderPipelineAbstract.html#method.recreate_framebuffers_if_none)
+    /// with a render pass compatible with [LinesRenderPass](../renderpass/struct.LinesRenderPass.html).
+    pub fn writes(self) -> &'static [&'static str] {
+        match self {
+            InsertionPoint::BeforeLighting => &[],
+            InsertionPoint::AfterPostProcess | InsertionPoint::Overlay => &[SWAPCHAIN],
+        }
+    }
+}
+
+
+/// Attachments allocated by the graph, by name.
+pub type GraphAttachments = HashMap<String, Arc<AttachmentImage<Format>>>;
+
+
+#[derive(Debug)]
+pub enum RenderGraphError {
+    /// A pass with that name has already been added.
+    DuplicatePass(String),
+    /// There is no pass with that name.
+    PassNotFound(String),
+    /// The pass wasn't added at an insertion point.
+    NotACustomPass(String),
+}
+
+
+/// Attachment allocated by the graph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AttachmentDesc {
+    pub format: Format,
+    /// Size relative to the main view, e.g. 0.5 for half resolution.
+    pub scale: f32,
+    pub usage: ImageUsage,
+}
+impl AttachmentDesc {
+    /// Attachment that can be rendered to, and read as an input attachment or sampled by later passes.
+    pub fn new(format: Format, scale: f32) -> Self {
+        Self {
+            format,
+            scale,
+            usage: ImageUsage {
+                color_attachment: true,
+                input_attachment: true,
+                sampled: true,
+                ..ImageUsage::none()
+            }
+        }
+    }
+}
+
+
+/// Declares a pass's name and the attachments it uses.
+#[derive(Clone, Debug)]
+pub struct PassDesc {
+    pub name: String,
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+    /// Attachments the graph allocates for this pass. They're available in `RenderInfo::graph_attachments`
+    /// to this pass and any later pass that reads them.
+    pub creates: Vec<(String, AttachmentDesc)>,
+}
+impl PassDesc {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            creates: Vec::new(),
+        }
+    }
+
+    pub fn reads(mut self, names: &[&str]) -> Self {
+        self.reads.extend(names.iter().map(|n| n.to_string()));
+        self
+    }
+
+    pub fn writes(mut self, names: &[&str]) -> Self {
+        self.writes.extend(names.iter().map(|n| n.to_string()));
+        self
+    }
+
+    /// Has the graph allocate an attachment, which the pass writes.
+    pub fn creates(mut self, name: &str, desc: AttachmentDesc) -> Self {
+        self.creates.push((name.to_string(), desc));
+        self.writes.push(name.to_string());
+        self
+    }
+}
+
+
+enum PassKind {
+    Pipeline(Box<dyn RenderPipelineAbstract>),
+    /// Command buffers are built by the renderer and passed to `execute`.
+    External,
+}
+
+
+struct GraphNode {
+    desc: PassDesc,
+    kind: PassKind,
+    enabled: bool,
+    /// Insertion point of custom passes.
+    point: Option<InsertionPoint>,
+}
+
+
+/// Work submitted to one queue since it was last waited on by another.
+struct QueueChain {
+    queue: Arc<Queue>,
+    future: Box<dyn GpuFuture>,
+    /// Nodes submitted to `future`.
+    pending: Vec<usize>,
+}
+
+
+/// Ordered list of passes drawing a frame of the main view.
+pub struct RenderGraph {
+    nodes: Vec<GraphNode>,
+    attachments: GraphAttachments,
+    /// Name of the attachment owning the image each aliasing attachment shares, so passes using either one
+    /// are ordered.
+    aliases: HashMap<String, String>,
+    /// Dimensions `attachments` were allocated for, `None` if they need to be reallocated.
+    allocated_dimensions: Option<[u32; 2]>,
+}
+
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        RenderGraph {
+            nodes: Vec::new(),
+            attachments: HashMap::new(),
+            aliases: HashMap::new(),
+            allocated_dimensions: None,
+        }
+    }
+
+
+    /// Adds a pass after all others.
+    pub fn add_pass(&mut self, desc: PassDesc, pipeline: Box<dyn RenderPipelineAbstract>) -> Result<(), RenderGraphError> {
+        let index = self.nodes.len();
+        self.insert(index, desc, PassKind::Pipeline(pipeline))
+    }
+
+
+    /// Adds a pass right before the pass named `before`.
+    pub fn add_pass_before(&mut self, before: &str, desc: PassDesc, pipeline: Box<dyn RenderPipelineAbstract>) -> Result<(), RenderGraphError> {
+        let index = self.index(before)?;
+        self.insert(index, desc, PassKind::Pipeline(pipeline))
+    }
+
+
+    /// Adds a pass right after the pass named `after`.
+    pub fn add_pass_after(&mut self, after: &str, desc: PassDesc, pipeline: Box<dyn RenderPipelineAbstract>) -> Result<(), RenderGraphError> {
+        let index = self.index(after)? + 1;
+        self.insert(index, desc, PassKind::Pipeline(pipeline))
+    }
+
+
+    /// Adds a custom pass at an insertion point, after the other passes there, or after the insertion point's
+    /// anchor if there are none. The attachments the insertion point implies are added to `desc`'s declarations.
+    pub fn add_pass_at(&mut self, point: InsertionPoint, desc: PassDesc, pipeline: Box<dyn RenderPipelineAbstract>) -> Result<(), RenderGraphError> {
+        let index = match self.nodes.iter().rposition(|n| n.point == Some(point)) {
+            Some(last) => last + 1,
+            None => self.index(point.anchor())? + 1,
+        };
+        let desc = desc.reads(point.reads()).writes(point.writes());
+        self.insert(index, desc, PassKind::Pipeline(pipeline))?;
+        self.nodes[index].point = Some(point);
+        Ok(())
+    }
+
+
+    /// Removes a pass added with `add_pass_at`, returning its pipeline.
+    pub fn remove_pass_at(&mut self, name: &str) -> Result<Box<dyn RenderPipelineAbstract>, RenderGraphError> {
+        if self.nodes[self.index(name)?].point.is_none() {
+            return Err(RenderGraphError::NotACustomPass(name.to_string()));
+        }
+        match self.remove_pass(name)? {
+            Some(pipeline) => Ok(pipeline),
+            None => Err(RenderGraphError::NotACustomPass(name.to_string())),
+        }
+    }
+
+
+    /// Names of the custom passes at an insertion point, in the order they run.
+    pub fn passes_at(&self, point: InsertionPoint) -> impl Iterator<Item=&str> {
+        self.nodes.iter().filter(move |n| n.point == Some(point)).map(|n| n.desc.name.as_str())
+    }
+
+
+    /// Adds a pass whose command buffers are built outside of the graph, and passed to `execute` every frame.
+    pub(crate) fn add_external_pass(&mut self, desc: PassDesc) -> Result<(), RenderGraphError> {
+        let index = self.nodes.len();
+        self.insert(index, desc, PassKind::External)
+    }
+
+
+    /// Removes a pass, returning its pipeline. Built-in passes can be removed too, e.g. to replace them.
+    pub fn remove_pass(&mut self, name: &str) -> Result<Option<Box<dyn RenderPipelineAbstract>>, RenderGraphError> {
+        let index = self.index(name)?;
+        self.allocated_dimensions = None;
+        Ok(match self.nodes.remove(index).kind {
+            PassKind::Pipeline(pipeline) => Some(pipeline),
+            PassKind::External => None,
+        })
+    }
+
+
+    /// Replaces the pipeline of a pass, keeping its position and declarations.
+    pub fn replace_pipeline(&mut self, name: &str, pipeline: Box<dyn RenderPipelineAbstract>) -> Result<(), RenderGraphError> {
+        let index = self.index(name)?;
+        self.nodes[index].kind = PassKind::Pipeline(pipeline);
+        Ok(())
+    }
+
+
+    /// Disabled passes are skipped, and nothing waits on them.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), RenderGraphError> {
+        let index = self.index(name)?;
+        self.nodes[index].enabled = enabled;
+        Ok(())
+    }
+
+
+    /// Names of all passes, in the order they run.
+    pub fn pass_names(&self) -> impl Iterator<Item=&str> {
+        self.nodes.iter().map(|n| n.desc.name.as_str())
+    }
+
+
+    /// Attachments allocated by the graph for the current dimensions.
+    pub fn attachments(&self) -> &GraphAttachments {
+        &self.attachments
+    }
+
+
+    pub fn remove_framebuffers(&mut self) {
+        for node in self.nodes.iter_mut() {
+            if let PassKind::Pipeline(p) = &mut node.kind {
+                p.remove_framebuffers();
+            }
+        }
+    }
+
+
+    pub fn recreate_framebuffers_if_none(&mut self, images: &[Arc<dyn ImageViewAccess + Send + Sync>], info: &RenderInfo) {
+        for node in self.nodes.iter_mut() {
+            if let PassKind::Pipeline(p) = &mut node.kind {
+                p.recreate_framebuffers_if_none(images, info);
+            }
+        }
+    }
+
+
+    fn index(&self, name: &str) -> Result<usize, RenderGraphError> {
+        self.nodes.iter().position(|n| n.desc.name == name).ok_or_else(|| RenderGraphError::PassNotFound(name.to_string()))
+    }
+
+
+    fn insert(&mut self, index: usize, desc: PassDesc, kind: PassKind) -> Result<(), RenderGraphError> {
+        if self.index(&desc.name).is_ok() {
+            return Err(RenderGraphError::DuplicatePass(desc.name));
+        }
+        self.nodes.insert(index, GraphNode { desc, kind, enabled: true, point: None });
+        self.allocated_dimensions = None;
+        Ok(())
+    }
+
+
+    /// Allocates the attachments passes create, if the passes or dimensions changed since they were last
+    /// allocated, sharing images between attachments whose lifetimes don't overlap. Returns true if they were
+    /// reallocated, in which case framebuffers referencing them have to be recreated.
+    pub(crate) fn allocate(&mut self, device: Arc<Device>, dimensions: [u32; 2]) -> bool {
+        if self.allocated_dimensions == Some(dimensions) {
+            return false;
+        }
+
+        let descs: Vec<&PassDesc> = self.nodes.iter().map(|n| &n.desc).collect();
+        let plan = plan_allocation(&descs, dimensions);
+        let images: Vec<Arc<AttachmentImage<Format>>> = plan.images.iter()
+            .map(|(desc, size)| AttachmentImage::with_usage(device.clone(), *size, desc.format, desc.usage).unwrap())
+            .collect();
+        let attachments: GraphAttachments = plan.attachments.iter()
+            .map(|(name, image)| (name.clone(), images[*image].clone()))
+            .collect();
+        if !attachments.is_empty() {
+            info!(Renderer, "Render graph allocated {} images for {} attachments", images.len(), attachments.len());
+        }
+
+        self.aliases = plan.aliases;
+        self.attachments = attachments;
+        self.allocated_dimensions = Some(dimensions);
+        true
+    }
+
+
+    /// Builds and submits the command buffers of all enabled passes in order, after `start`. `external` has the
+    /// command buffers of external passes, which are skipped if they have none. Passes in `skipped` don't run
+    /// this frame.
+    ///
+    /// Each queue's work is chained in its own future, and a pass waits on another queue's future with a
+    /// semaphore only when it depends on a pass submitted there, i.e. it reads what that pass wrote, or writes
+    /// what it read or wrote. Attachments sharing an image count as the same one. Returns the future on the main queue, which has waited on all other queues.
+    pub(crate) fn execute(&mut self, info: &RenderInfo, start: Box<dyn GpuFuture>,
+                          mut external: HashMap<&str, Vec<(AutoCommandBuffer, Arc<Queue>)>>, skipped: &[&str]) -> Box<dyn GpuFuture> {
+        let queue_key = |q: &Arc<Queue>| (q.family().id(), q.id_within_family());
+        let mut chains = vec![QueueChain { queue: info.queue_main.clone(), future: start, pending: Vec::new() }];
+        // index in `chains` each submitted node ran on
+        let mut ran_on: HashMap<usize, usize> = HashMap::new();
+        let mut hazards = HazardTracker::default();
+        let aliases = &self.aliases;
+
+        for (i, node) in self.nodes.iter_mut().enumerate().filter(|(_, n)| n.enabled && !skipped.contains(&n.desc.name.as_str())) {
+            let cbs = match &mut node.kind {
+                PassKind::Pipeline(p) => vec![p.build_command_buffer(info)],
+                PassKind::External => external.remove(node.desc.name.as_str()).unwrap_or_default(),
+            };
+            if cbs.is_empty() {
+                continue;
+            }
+
+            let queue = cbs[0].1.clone();
+            let chain = match chains.iter().position(|c| queue_key(&c.queue) == queue_key(&queue)) {
+                Some(chain) => chain,
+                None => {
+                    chains.push(QueueChain { queue: queue.clone(), future: Box::new(vulkano::sync::now(info.device.clone())), pending: Vec::new() });
+                    chains.len() - 1
+                }
+            };
+
+            let reads = resolve_aliases(aliases, &node.desc.reads);
+            let writes = resolve_aliases(aliases, &node.desc.writes);
+            let dependencies = hazards.dependencies(&reads, &writes);
+            // work from other queues this pass depends on, that hasn't been waited on yet
+            let mut wait_on: Vec<usize> = dependencies.iter()
+                .map(|d| ran_on[d])
+                .filter(|c| *c != chain)
+                .filter(|c| dependencies.iter().any(|d| chains[*c].pending.contains(d)))
+                .collect();
+            wait_on.sort();
+            wait_on.dedup();
+            for other in wait_on {
+                let signal = take_future(&mut chains[other], info).then_signal_semaphore_and_flush().unwrap();
+                chains[other].pending.clear();
+                let future = take_future(&mut chains[chain], info);
+                chains[chain].future = Box::new(future.join(signal));
+            }
+
+            for (cb, q) in cbs {
+                let future = take_future(&mut chains[chain], info);
+                chains[chain].future = Box::new(future.then_execute(q, cb).unwrap());
+            }
+            chains[chain].pending.push(i);
+            ran_on.insert(i, chain);
+
+            hazards.record(i, &reads, &writes);
+        }
+
+        // the frame is done when all queues are
+        let mut main = chains.remove(0).future;
+        for chain in chains.into_iter().filter(|c| !c.pending.is_empty()) {
+            main = Box::new(main.join(chain.future.then_signal_semaphore_and_flush().unwrap()));
+        }
+        main
+    }
+}
+
+
+/// Takes a chain's future, leaving an empty one in its place.
+fn take_future(chain: &mut QueueChain, info: &RenderInfo) -> Box<dyn GpuFuture> {
+    std::mem::replace(&mut chain.future, Box::new(vulkano::sync::now(info.device.clone())))
+}
+
+
+/// Which image each attachment created by the graph uses.
+struct AllocationPlan {
+    /// Description and size of each image.
+    images: Vec<(AttachmentDesc, [u32; 2])>,
+    /// Index in `images` of each attachment.
+    attachments: HashMap<String, usize>,
+    /// Attachments sharing an image with one created earlier, and the name of that one.
+    aliases: HashMap<String, String>,
+}
+
+
+/// Assigns images to the attachments `passes` create. Attachments with the same format, size and usage share
+/// an image if no pass between the one creating the first and the last one using it uses the second.
+fn plan_allocation(passes: &[&PassDesc], dimensions: [u32; 2]) -> AllocationPlan {
+    let mut plan = AllocationPlan { images: Vec::new(), attachments: HashMap::new(), aliases: HashMap::new() };
+    // name of the attachment that first used each image, and index of the last pass using it
+    let mut owners: Vec<(String, usize)> = Vec::new();
+    for (first, pass) in passes.iter().enumerate() {
+        for (name, desc) in pass.creates.iter() {
+            let last = passes.iter().rposition(|p| p.reads.contains(name) || p.writes.contains(name))
+                .unwrap_or(first);
+            let size = [((dimensions[0] as f32 * desc.scale) as u32).max(1),
+                        ((dimensions[1] as f32 * desc.scale) as u32).max(1)];
+            let shared = plan.images.iter().zip(owners.iter())
+                .position(|((d, s), (_, end))| *end < first && d.format == desc.format && d.usage == desc.usage && *s == size);
+            let image = match shared {
+                Some(image) => {
+                    owners[image].1 = last;
+                    plan.aliases.insert(name.clone(), owners[image].0.clone());
+                    image
+                },
+                None => {
+                    plan.images.push((*desc, size));
+                    owners.push((name.clone(), last));
+                    plan.images.len() - 1
+                }
+            };
+            plan.attachments.insert(name.clone(), image);
+        }
+    }
+    plan
+}
+
+
+/// Replaces attachments sharing an image with the attachment owning it.
+fn resolve_aliases(aliases: &HashMap<String, String>, names: &[String]) -> Vec<String> {
+    names.iter().map(|n| aliases.get(n).unwrap_or(n).clone()).collect()
+}
+
+
+/// Passes that last wrote and read each attachment, to infer which earlier passes a pass depends on.
+#[derive(Default)]
+struct HazardTracker {
+    last_writer: HashMap<String, usize>,
+    /// Passes that read an attachment since it was last written.
+    readers: HashMap<String, Vec<usize>>,
+}
+impl HazardTracker {
+    /// Earlier passes that a pass reading and writing these attachments has to run after.
+    fn dependencies(&self, reads: &[String], writes: &[String]) -> HashSet<usize> {
+        let mut dependencies = HashSet::new();
+        for name in reads.iter() {
+            dependencies.extend(self.last_writer.get(name));
+        }
+        for name in writes.iter() {
+            dependencies.extend(self.last_writer.get(name));
+            dependencies.extend(self.readers.get(name).into_iter().flatten());
+        }
+        dependencies
+    }
+
+    fn record(&mut self, pass: usize, reads: &[String], writes: &[String]) {
+        for name in reads.iter() {
+            self.readers.entry(name.clone()).or_insert_with(Vec::new).push(pass);
+        }
+        for name in writes.iter() {
+            self.last_writer.insert(name.clone(), pass);
+            self.readers.remove(name);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn rgba(scale: f32) -> AttachmentDesc {
+        AttachmentDesc::new(Format::R8G8B8A8Unorm, scale)
+    }
+
+    #[test]
+    fn attachments_with_disjoint_lifetimes_share_an_image() {
+        let passes = vec![
+            PassDesc::new("a").creates("mask", rgba(1.0)),
+            PassDesc::new("b").reads(&["mask"]).writes(&[SWAPCHAIN]),
+            PassDesc::new("c").creates("blur", rgba(1.0)),
+            PassDesc::new("d").reads(&["blur"]),
+        ];
+        let plan = plan_allocation(&passes.iter().collect::<Vec<_>>(), [800, 600]);
+        assert_eq!(plan.images.len(), 1);
+        assert_eq!(plan.attachments["mask"], plan.attachments["blur"]);
+        assert_eq!(plan.aliases.get("blur").map(|s| s.as_str()), Some("mask"));
+        assert!(plan.aliases.get("mask").is_none());
+    }
+
+    #[test]
+    fn attachments_with_overlapping_lifetimes_get_their_own_images() {
+        let passes = vec![
+            PassDesc::new("a").creates("mask", rgba(1.0)),
+            PassDesc::new("b").creates("blur", rgba(1.0)),
+            PassDesc::new("c").reads(&["mask", "blur"]),
+        ];
+        let plan = plan_allocation(&passes.iter().collect::<Vec<_>>(), [800, 600]);
+        assert_eq!(plan.images.len(), 2);
+        assert_ne!(plan.attachments["mask"], plan.attachments["blur"]);
+        assert!(plan.aliases.is_empty());
+    }
+
+    #[test]
+    fn attachments_with_different_descs_get_their_own_images() {
+        let passes = vec![
+            PassDesc::new("a").creates("mask", rgba(1.0)),
+            PassDesc::new("b").creates("half", rgba(0.5)),
+            PassDesc::new("c").creates("hdr", AttachmentDesc::new(Format::R16G16B16A16Sfloat, 1.0)),
+        ];
+        let plan = plan_allocation(&passes.iter().collect::<Vec<_>>(), [800, 600]);
+        assert_eq!(plan.images.len(), 3);
+        assert_eq!(plan.images[plan.attachments["half"]].1, [400, 300]);
+    }
+
+    #[test]
+    fn image_is_reused_after_the_last_alias_ends() {
+        let passes = vec![
+            PassDesc::new("a").creates("first", rgba(1.0)),
+            PassDesc::new("b").creates("second", rgba(1.0)).reads(&["first"]),
+            PassDesc::new("c").creates("third", rgba(1.0)),
+            PassDesc::new("d").reads(&["second", "third"]),
+        ];
+        let plan = plan_allocation(&passes.iter().collect::<Vec<_>>(), [800, 600]);
+        // "second" overlaps "first" in b, and "third" overlaps "second" in d, but "first" is done by c
+        assert_eq!(plan.images.len(), 2);
+        assert_eq!(plan.attachments["first"], plan.attachments["third"]);
+        assert_eq!(plan.aliases.get("third").map(|s| s.as_str()), Some("first"));
+    }
+
+    #[test]
+    fn hazards() {
+        let mut hazards = HazardTracker::default();
+        hazards.record(0, &[], &names(&["albedo"]));
+        // read after write
+        assert_eq!(hazards.dependencies(&names(&["albedo"]), &[]), [0].iter().cloned().collect());
+        hazards.record(1, &names(&["albedo"]), &[]);
+        hazards.record(2, &names(&["albedo"]), &[]);
+        // reads don't depend on other reads
+        assert_eq!(hazards.dependencies(&names(&["albedo"]), &[]), [0].iter().cloned().collect());
+        // write after read and write
+        assert_eq!(hazards.dependencies(&[], &names(&["albedo"])), [0, 1, 2].iter().cloned().collect());
+        hazards.record(3, &[], &names(&["albedo"]));
+        assert_eq!(hazards.dependencies(&[], &names(&["albedo"])), [3].iter().cloned().collect());
+        assert!(hazards.dependencies(&names(&["normal"]), &names(&["position"])).is_empty());
+    }
+
+    #[test]
+    fn passes_using_aliased_attachments_are_ordered() {
+        let passes = vec![
+            PassDesc::new("a").creates("mask", rgba(1.0)),
+            PassDesc::new("b").reads(&["mask"]),
+            PassDesc::new("c").creates("blur", rgba(1.0)),
+            PassDesc::new("d").reads(&["blur"]),
+        ];
+        let plan = plan_allocation(&passes.iter().collect::<Vec<_>>(), [800, 600]);
+        let mut hazards = HazardTracker::default();
+        for (i, pass) in passes.iter().enumerate().take(2) {
+            hazards.record(i, &resolve_aliases(&plan.aliases, &pass.reads), &resolve_aliases(&plan.aliases, &pass.writes));
+        }
+        // c overwrites the image b reads
+        let dependencies = hazards.dependencies(&resolve_aliases(&plan.aliases, &passes[2].reads),
+                                                &resolve_aliases(&plan.aliases, &passes[2].writes));
+        assert_eq!(dependencies, [0, 1].iter().cloned().collect());
+    }
+}
@@ -26,14 +26,18 @@ pub mod buffer;
 pub mod camera;
 pub mod compute;
 pub mod cpu_pool;
+pub mod debug_draw;
 pub mod geometry;
+pub mod graph;
 pub mod memory;
 #[macro_use] mod names;
 pub mod pipeline;
+pub mod readback;
 pub mod registry;
 pub mod renderer;
 pub mod renderpass;
 pub mod shader;
+pub mod view;
 pub mod vulkano_win;
 
 
@@ -6,10 +6,9 @@ use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use vulkano::device::Queue;
 use vulkano::format::{ClearValue, R16G16B16A16Sfloat, R8G8B8A8Srgb};
 use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPass, RenderPassDesc, Subpass, RenderPassAbstract};
-use vulkano::image::{SwapchainImage, ImmutableImage};
+use vulkano::image::{ImageViewAccess, ImmutableImage};
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
-use winit::Window;
 
 use crate::geometry::VertexPosition;
 use crate::pipeline::RenderPipelineAbstract;
@@ -17,6 +16,7 @@ use crate::renderer::RenderInfo;
 use crate::renderpass::DeferredLightingRenderPass;
 use crate::shader::deferred_lighting as DeferredLightingShaders;
 use crate::buffer::CpuAccessibleBufferXalloc;
+use crate::compute::AmbientOcclusionCompute;
 use vulkano::sampler::{Sampler, Filter, MipmapMode, SamplerAddressMode};
 
 
@@ -28,7 +28,9 @@ pub struct DeferredLightingRenderPipeline {
     irr_cubemap: Arc<ImmutableImage<R16G16B16A16Sfloat>>,
     rad_cubemap: Arc<ImmutableImage<R16G16B16A16Sfloat>>,
     brdf_lookup: Arc<ImmutableImage<R8G8B8A8Srgb>>,
-    linear_sampler: Arc<Sampler>
+    linear_sampler: Arc<Sampler>,
+    nearest_sampler: Arc<Sampler>,
+    ao_compute: AmbientOcclusionCompute,
 }
 
 
@@ -75,7 +77,11 @@ impl DeferredLightingRenderPipeline {
             brdf_lookup: info.tex_registry.get("BRDF_Lookup_Smith").unwrap(),
             linear_sampler: Sampler::new(info.device.clone(), Filter::Linear, Filter::Linear, MipmapMode::Linear,
                 SamplerAddressMode::Repeat, SamplerAddressMode::Repeat, SamplerAddressMode::Repeat,
-                0.0, 4.0, 0.0, 4.0).unwrap()
+                0.0, 4.0, 0.0, 4.0).unwrap(),
+            nearest_sampler: Sampler::new(info.device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Nearest,
+                SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                0.0, 1.0, 0.0, 0.0).unwrap(),
+            ao_compute: AmbientOcclusionCompute::new(info.device.clone()),
         }
     }
 }
@@ -100,10 +106,12 @@ impl RenderPipelineAbstract for DeferredLightingRenderPipeline {
             .add_sampled_image(self.irr_cubemap.clone(), self.linear_sampler.clone()).unwrap()
             .add_sampled_image(self.rad_cubemap.clone(), self.linear_sampler.clone()).unwrap()
             .add_sampled_image(self.brdf_lookup.clone(), self.linear_sampler.clone()).unwrap()
+            .add_sampled_image(info.attachments.ao.clone(), self.nearest_sampler.clone()).unwrap()
             .build().unwrap());
 
-        let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family())
-            .unwrap()
+        let cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family())
+            .unwrap();
+        let mut cb = self.ao_compute.record(cb, info)
             .begin_render_pass(
                 self.framebuffers.as_ref().unwrap()[info.image_num].clone(), false,
                 vec![ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, [0.0, 0.0, 0.0, 1.0].into(), [0.0, 0.0, 0.0, 1.0].into()]).unwrap()
@@ -122,7 +130,7 @@ impl RenderPipelineAbstract for DeferredLightingRenderPipeline {
                   vec![self.fullscreen_vertex_buffer.clone()],
                   descriptor_set, DeferredLightingShaders::fragment::ty::Constants {
                     view: info.view_mat.into(),
-                    view_pos: info.camera_transform.position.into(),
+                    view_pos: view_pos(info).into(),
                     debug_vis_mode: info.debug_visualize_setting
                 }).unwrap();
 
@@ -131,7 +139,7 @@ impl RenderPipelineAbstract for DeferredLightingRenderPipeline {
         (cb.build().unwrap(), info.queue_main.clone())
     }
 
-    fn recreate_framebuffers_if_none(&mut self, images: &Vec<Arc<SwapchainImage<Window>>>, info: &RenderInfo) {
+    fn recreate_framebuffers_if_none(&mut self, images: &[Arc<dyn ImageViewAccess + Send + Sync>], info: &RenderInfo) {
         if self.get_framebuffers_mut().is_none() {
             let new_framebuffers = Some(images.iter().map(|_| {
                 let arc: Arc<dyn FramebufferAbstract + Send + Sync> = Arc::new(Framebuffer::start(self.get_renderpass().clone())
@@ -149,3 +157,17 @@ impl RenderPipelineAbstract for DeferredLightingRenderPipeline {
         }
     }
 }
+
+
+/// Camera position for the view vector, or for orthographic projections the direction towards the camera with w = 0.
+fn view_pos(info: &RenderInfo) -> [f32; 4] {
+    if info.unjittered_proj_mat.z.w == 0.0 {
+        // third row of the view rotation is the camera's +z (backwards) axis in world space
+        let view = &info.view_mat;
+        [view.x.z, view.y.z, view.z.z, 0.0]
+    }
+    else {
+        let pos = info.camera_transform.position;
+        [pos.x, pos.y, pos.z, 1.0]
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use vulkano::buffer::BufferUsage;
@@ -6,11 +7,12 @@ use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use vulkano::descriptor::DescriptorSet;
 use vulkano::device::Queue;
 use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPass, RenderPassDesc, Subpass, RenderPassAbstract};
-use vulkano::image::SwapchainImage;
+use vulkano::format::B8G8R8A8Srgb;
+use vulkano::image::{AttachmentImage, ImageViewAccess};
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::pipeline::depth_stencil::{DepthStencil, Compare};
 use vulkano::sampler::{Sampler, Filter, SamplerAddressMode, MipmapMode};
-use winit::Window;
 
 use crate::cpu_pool::XallocCpuBufferPool;
 use crate::geometry::{DeferredShadingVertex, VertexPositionUV};
@@ -30,8 +32,12 @@ pub struct DeferredShadingRenderPipeline {
     pub framebuffers: Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>,
     renderpass: Arc<RenderPass<DeferredShadingRenderPass>>,
     voxel_uniform_buffer_pool: XallocCpuBufferPool<DeferredShadingShaders::vertex::ty::InstanceData>,
+    frame_uniform_buffer_pool: XallocCpuBufferPool<DeferredShadingShaders::vertex::ty::FrameData>,
     // TODO: texture bindings per material
     voxel_texture_descriptors: Arc<dyn DescriptorSet + Send + Sync>,
+    linear_sampler: Arc<Sampler>,
+    /// Texture descriptors of materials showing an offscreen view, by view name, with the target they were built for.
+    render_target_descriptors: HashMap<String, (Arc<AttachmentImage<B8G8R8A8Srgb>>, Arc<dyn DescriptorSet + Send + Sync>)>,
     skybox_vertex_buffer: Arc<CpuAccessibleBufferXalloc<[VertexPositionUV]>>,
     skybox_index_buffer: Arc<CpuAccessibleBufferXalloc<[u32]>>,
 }
@@ -55,7 +61,12 @@ impl DeferredShadingRenderPipeline {
                 .triangle_list()
                 .viewports_dynamic_scissors_irrelevant(1)
                 .fragment_shader(fs.main_entry_point(), ())
-                .depth_stencil_simple_depth()
+                // at the far plane, so it has to pass against the cleared depth
+                .depth_stencil(DepthStencil {
+                    depth_write: false,
+                    depth_compare: info.depth_mode.compare(Compare::LessOrEqual),
+                    ..DepthStencil::simple_depth_test()
+                })
                 .render_pass(Subpass::from(renderpass.clone(), 0).unwrap())
                 .build(info.device.clone())
                 .unwrap())
@@ -72,7 +83,7 @@ impl DeferredShadingRenderPipeline {
                 .triangle_list()
                 .viewports_dynamic_scissors_irrelevant(1)
                 .fragment_shader(fs.main_entry_point(), ())
-                .depth_stencil_simple_depth()
+                .depth_stencil(info.depth_mode.depth_test())
                 .render_pass(Subpass::from(renderpass.clone(), 1).unwrap())
                 .build(info.device.clone())
                 .unwrap())
@@ -135,7 +146,10 @@ impl DeferredShadingRenderPipeline {
             framebuffers: None,
             renderpass,
             voxel_uniform_buffer_pool: XallocCpuBufferPool::<DeferredShadingShaders::vertex::ty::InstanceData>::new(info.device.clone(), BufferUsage::all()),
+            frame_uniform_buffer_pool: XallocCpuBufferPool::<DeferredShadingShaders::vertex::ty::FrameData>::new(info.device.clone(), BufferUsage::all()),
             voxel_texture_descriptors,
+            linear_sampler,
+            render_target_descriptors: HashMap::new(),
             skybox_vertex_buffer,
             skybox_index_buffer,
         }
@@ -143,6 +157,8 @@ impl DeferredShadingRenderPipeline {
 }
 
 const CLEAR_BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+/// For position and velocity. Geometry writes its linear depth to position.w, so w = 0 marks sky pixels for SSAO.
+const CLEAR_ZERO: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
 
 impl RenderPipelineAbstract for DeferredShadingRenderPipeline {
     fn get_framebuffers_mut(&mut self) -> &mut Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>> {
@@ -156,10 +172,44 @@ impl RenderPipelineAbstract for DeferredShadingRenderPipeline {
 
     fn build_command_buffer(&mut self, info: &RenderInfo) -> (AutoCommandBuffer, Arc<Queue>) {
         let mut voxel_descriptor_sets = Vec::new();
+        let mut texture_descriptor_sets = Vec::new();
         let lock = info.render_queues.read().unwrap();
         for entry in lock.meshes.iter() {
+            // materials can sample the color target of an offscreen view, e.g. for mirrors and monitors, but
+            // not the view being drawn
+            let albedo_map_name = &entry.material.albedo_map_name;
+            let target = match info.view_name {
+                Some(ref view_name) if view_name == albedo_map_name => None,
+                _ => info.tex_registry.get_render_target(albedo_map_name),
+            };
+            let texture_descriptors = match target {
+                Some(target) => {
+                    let cached = self.render_target_descriptors.get(albedo_map_name)
+                        .filter(|(image, _)| Arc::ptr_eq(image, &target))
+                        .map(|(_, descriptors)| descriptors.clone());
+                    match cached {
+                        Some(descriptors) => descriptors,
+                        None => {
+                            let descriptors = Arc::new(PersistentDescriptorSet::start(self.voxel_shading_pipeline.clone(), 0)
+                                .add_sampled_image(target.clone(), self.linear_sampler.clone()).unwrap()
+                                .add_sampled_image(info.tex_registry.get("test_normal").unwrap().clone(), self.linear_sampler.clone()).unwrap()
+                                .add_sampled_image(info.tex_registry.get("black").unwrap().clone(), self.linear_sampler.clone()).unwrap()
+                                .add_sampled_image(info.tex_registry.get("black").unwrap().clone(), self.linear_sampler.clone()).unwrap()
+                                .build().unwrap()
+                            ) as Arc<dyn DescriptorSet + Send + Sync>;
+                            // replaces the descriptors of a target that was resized
+                            self.render_target_descriptors.insert(albedo_map_name.clone(), (target, descriptors.clone()));
+                            descriptors
+                        }
+                    }
+                },
+                None => self.voxel_texture_descriptors.clone()
+            };
+            texture_descriptor_sets.push(texture_descriptors);
+
             let uniform_data = DeferredShadingShaders::vertex::ty::InstanceData {
-                world: entry.transform.clone().into()
+                world: entry.transform.clone().into(),
+                prev_world: entry.prev_transform.clone().into()
             };
 
             let subbuffer = self.voxel_uniform_buffer_pool.next(uniform_data).unwrap();
@@ -168,11 +218,32 @@ impl RenderPipelineAbstract for DeferredShadingRenderPipeline {
                 .build().unwrap()
             ));
         };
+        // release the targets of removed and resized views
+        self.render_target_descriptors.retain(|name, (image, _)|
+            info.tex_registry.get_render_target(name).map_or(false, |target| Arc::ptr_eq(&target, image)));
+
+        let frame_subbuffer = self.frame_uniform_buffer_pool.next(DeferredShadingShaders::vertex::ty::FrameData {
+            view_proj: (info.unjittered_proj_mat * info.view_mat).into(),
+            prev_view_proj: (info.prev_proj_mat * info.prev_view_mat).into(),
+        }).unwrap();
+        let frame_descriptor_set = Arc::new(PersistentDescriptorSet::start(self.voxel_shading_pipeline.clone(), 2)
+            .add_buffer(frame_subbuffer).unwrap()
+            .build().unwrap()
+        );
+
+        // meshes hidden behind the occluders in the last Hi-Z pyramid read back
+        let occluded: Vec<bool> = {
+            let hiz = info.hiz_compute.lock();
+            lock.meshes.iter().map(|entry| match entry.bounds {
+                Some((min, max)) if info.occlusion_culling_info.enabled => hiz.is_occluded(min, max, &entry.transform),
+                _ => false
+            }).collect()
+        };
 
         let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family())
             .unwrap()
             .begin_render_pass(self.framebuffers.as_ref().unwrap()[info.image_num].clone(), false,
-                               vec![CLEAR_BLACK.into(), CLEAR_BLACK.into(), CLEAR_BLACK.into(), CLEAR_BLACK.into(), CLEAR_BLACK.into(), 1f32.into()]).unwrap()
+                               vec![CLEAR_ZERO.into(), CLEAR_BLACK.into(), CLEAR_BLACK.into(), CLEAR_BLACK.into(), CLEAR_BLACK.into(), CLEAR_ZERO.into(), info.depth_mode.clear_depth().into()]).unwrap()
                 .draw_indexed(self.skybox_pipeline.clone(), &DynamicState {
                     line_width: None,
                     viewports: Some(vec![Viewport {
@@ -191,10 +262,11 @@ impl RenderPipelineAbstract for DeferredShadingRenderPipeline {
                                 matrix: (info.proj_mat.clone() * Matrix4::from(info.camera_transform.rotation)).into(),
                                 sun_rotation: 0.0,
                                 sun_transit: 0.4,
+                                far_depth: info.depth_mode.clear_depth(),
                             }).unwrap()
             .next_subpass(false).unwrap();
 
-        for (i, entry) in lock.meshes.iter().enumerate() {
+        for (i, entry) in lock.meshes.iter().enumerate().filter(|(i, _)| !occluded[*i]) {
             cb = cb.draw_indexed(self.voxel_shading_pipeline.clone(), &DynamicState {
                 line_width: None,
                 viewports: Some(vec![Viewport {
@@ -209,7 +281,7 @@ impl RenderPipelineAbstract for DeferredShadingRenderPipeline {
             },
                                  vec![entry.vertex_group.vertex_buffer.clone()],
                                  entry.vertex_group.index_buffer.clone(),
-                                 (self.voxel_texture_descriptors.clone(), voxel_descriptor_sets[i].clone()),
+                                 (texture_descriptor_sets[i].clone(), voxel_descriptor_sets[i].clone(), frame_descriptor_set.clone()),
                                  DeferredShadingShaders::vertex::ty::Constants {
                                      view: info.view_mat.into(),
                                      proj: info.proj_mat.into(),
@@ -220,7 +292,7 @@ impl RenderPipelineAbstract for DeferredShadingRenderPipeline {
         (cb.build().unwrap(), info.queue_main.clone())
     }
 
-    fn recreate_framebuffers_if_none(&mut self, images: &Vec<Arc<SwapchainImage<Window>>>, info: &RenderInfo) {
+    fn recreate_framebuffers_if_none(&mut self, images: &[Arc<dyn ImageViewAccess + Send + Sync>], info: &RenderInfo) {
         if self.get_framebuffers_mut().is_none() {
             let new_framebuffers = Some(images.iter().map(|_image| {
                 let arc: Arc<dyn FramebufferAbstract + Send + Sync> = Arc::new(Framebuffer::start(self.get_renderpass().clone())
@@ -229,6 +301,7 @@ impl RenderPipelineAbstract for DeferredShadingRenderPipeline {
                     .add(info.attachments.albedo.clone()).unwrap()
                     .add(info.attachments.roughness.clone()).unwrap()
                     .add(info.attachments.metallic.clone()).unwrap()
+                    .add(info.attachments.velocity.clone()).unwrap()
                     .add(info.attachments.main_depth.clone()).unwrap()
                     .build().unwrap());
                 arc
@@ -4,7 +4,7 @@ use imgui::internal::RawWrapper;
 use vulkano::device::Queue;
 use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, Subpass, RenderPassAbstract};
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
-use vulkano::image::{ImmutableImage, SwapchainImage};
+use vulkano::image::{ImageViewAccess, ImmutableImage};
 use vulkano::sampler::{Sampler, Filter, MipmapMode, SamplerAddressMode};
 use vulkano::buffer::BufferUsage;
 use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, AutoCommandBuffer};
@@ -15,7 +15,6 @@ use vulkano::format::R8G8B8A8Srgb;
 use crate::renderer::RenderInfo;
 use crate::pipeline::RenderPipelineAbstract;
 use crate::buffer::CpuAccessibleBufferXalloc;
-use winit::Window;
 use vulkano::sync::GpuFuture;
 
 
@@ -253,7 +252,7 @@ impl RenderPipelineAbstract for ImguiRenderPipeline {
         unimplemented!();
     }
 
-    fn recreate_framebuffers_if_none(&mut self, images: &Vec<Arc<SwapchainImage<Window>>>, _info: &RenderInfo) {
+    fn recreate_framebuffers_if_none(&mut self, images: &[Arc<dyn ImageViewAccess + Send + Sync>], _info: &RenderInfo) {
         if self.get_framebuffers_mut().is_none() {
             let new_framebuffers = Some(images.iter().map(|image| {
                 let arc: Arc<dyn FramebufferAbstract + Send + Sync> = Arc::new(Framebuffer::start(self.get_renderpass().clone())
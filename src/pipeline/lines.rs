@@ -1,28 +1,38 @@
 use std::sync::Arc;
 
 use cgmath::Matrix4;
-use vulkano::buffer::BufferUsage;
+use vulkano::buffer::{BufferUsage, TypedBufferAccess};
 use vulkano::command_buffer::{AutoCommandBufferBuilder, AutoCommandBuffer, DynamicState};
 use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use vulkano::device::Queue;
 use vulkano::framebuffer::{FramebufferAbstract, RenderPass, RenderPassDesc, Subpass, RenderPassAbstract};
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::pipeline::depth_stencil::{DepthStencil, Compare};
+use vulkano::pipeline::vertex::{BufferlessDefinition, BufferlessVertices};
 
-use crate::geometry::VertexPositionColorAlpha;
-use crate::renderer::RenderInfo;
+use crate::geometry::{VertexGroup, VertexPositionColorAlpha, VertexPositionColorWidth};
+use crate::renderer::{RenderInfo, LineRenderMode};
 use crate::renderpass::LinesRenderPass;
 use crate::shader::lines as LinesShaders;
 use crate::pipeline::RenderPipelineAbstract;
 use crate::cpu_pool::XallocCpuBufferPool;
+use crate::buffer::CpuAccessibleBufferXalloc;
 use vulkano::format::ClearValue;
 
 
 pub struct LinesRenderPipeline {
     vulkan_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// Pipelines for `DebugDraw` shapes as hardware lines, indexed by whether they're depth tested.
+    debug_pipelines: [Arc<dyn GraphicsPipelineAbstract + Send + Sync>; 2],
+    /// Screen-space expanded line pipelines, indexed by whether they're depth tested.
+    wide_pipelines: [Arc<dyn GraphicsPipelineAbstract + Send + Sync>; 2],
     pub framebuffers: Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>,
     renderpass: Arc<RenderPass<LinesRenderPass>>,
     uniform_buffer_pool: XallocCpuBufferPool<LinesShaders::vertex::ty::Data>,
+    /// `LineRenderQueue::chunk_lines_vg` expanded into segments for `LineRenderMode::Wide`, rebuilt when
+    /// the vertex group is replaced. `None` in the second field if there are no chunk lines.
+    chunk_segments: Option<(Arc<VertexGroup<VertexPositionColorAlpha>>, Option<Arc<CpuAccessibleBufferXalloc<[VertexPositionColorWidth]>>>)>,
 }
 
 
@@ -30,6 +40,8 @@ impl LinesRenderPipeline {
     pub fn new(info: &RenderInfo) -> LinesRenderPipeline {
         let vs = LinesShaders::vertex::Shader::load(info.device.clone()).expect("failed to create shader module");
         let fs = LinesShaders::fragment::Shader::load(info.device.clone()).expect("failed to create shader module");
+        let wide_vs = LinesShaders::wide_vertex::Shader::load(info.device.clone()).expect("failed to create shader module");
+        let wide_fs = LinesShaders::wide_fragment::Shader::load(info.device.clone()).expect("failed to create shader module");
 
         let renderpass= Arc::new(
             LinesRenderPass {}
@@ -37,24 +49,85 @@ impl LinesRenderPipeline {
                 .unwrap()
         );
 
-        let pipeline = Arc::new(GraphicsPipeline::start()
-            .vertex_input_single_buffer::<VertexPositionColorAlpha>()
-            .vertex_shader(vs.main_entry_point(), ())
-            .line_list()
-            .viewports_dynamic_scissors_irrelevant(1)
-            .fragment_shader(fs.main_entry_point(), ())
-            .depth_stencil_simple_depth()
-            .blend_alpha_blending()
-            .render_pass(Subpass::from(renderpass.clone(), 0).unwrap())
-            .build(info.device.clone())
-            .unwrap());
+        macro_rules! lines_pipeline {
+            ($vertex:ty, $depth_stencil:expr) => {
+                Arc::new(GraphicsPipeline::start()
+                    .vertex_input_single_buffer::<$vertex>()
+                    .vertex_shader(vs.main_entry_point(), ())
+                    .line_list()
+                    .viewports_dynamic_scissors_irrelevant(1)
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .depth_stencil($depth_stencil)
+                    .blend_alpha_blending()
+                    .render_pass(Subpass::from(renderpass.clone(), 0).unwrap())
+                    .build(info.device.clone())
+                    .unwrap()) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>
+            }
+        }
+        let pipeline = lines_pipeline!(VertexPositionColorAlpha, info.depth_mode.depth_test());
+        let debug_pipelines = [lines_pipeline!(VertexPositionColorWidth, DepthStencil::disabled()),
+                               lines_pipeline!(VertexPositionColorWidth, info.depth_mode.depth_test())];
+
+        // anti-aliased edges are blended, so wide lines don't write depth
+        let depth_tested = DepthStencil {
+            depth_write: false,
+            depth_compare: info.depth_mode.compare(Compare::LessOrEqual),
+            ..DepthStencil::simple_depth_test()
+        };
+        macro_rules! wide_pipeline {
+            ($depth_stencil:expr) => {
+                Arc::new(GraphicsPipeline::start()
+                    .vertex_input(BufferlessDefinition)
+                    .vertex_shader(wide_vs.main_entry_point(), ())
+                    .triangle_list()
+                    .viewports_dynamic_scissors_irrelevant(1)
+                    .fragment_shader(wide_fs.main_entry_point(), ())
+                    .depth_stencil($depth_stencil)
+                    .blend_alpha_blending()
+                    .render_pass(Subpass::from(renderpass.clone(), 0).unwrap())
+                    .build(info.device.clone())
+                    .unwrap()) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>
+            }
+        }
+        let wide_pipelines = [wide_pipeline!(DepthStencil::disabled()), wide_pipeline!(depth_tested)];
 
         LinesRenderPipeline {
             vulkan_pipeline: pipeline,
+            debug_pipelines,
+            wide_pipelines,
             framebuffers: None,
             renderpass,
             uniform_buffer_pool: XallocCpuBufferPool::<LinesShaders::vertex::ty::Data>::new(info.device.clone(), BufferUsage::all()),
+            chunk_segments: None,
+        }
+    }
+
+
+    /// Returns `chunk_lines_vg` expanded into segments at the default width, reading it back only if it
+    /// changed since the last call.
+    fn chunk_segments(&mut self, info: &RenderInfo, chunk_lines_vg: &Arc<VertexGroup<VertexPositionColorAlpha>>)
+        -> Option<Arc<CpuAccessibleBufferXalloc<[VertexPositionColorWidth]>>> {
+        match &self.chunk_segments {
+            Some((vg, segments)) if Arc::ptr_eq(vg, chunk_lines_vg) => return segments.clone(),
+            _ => {}
         }
+
+        let chunk_vertices = chunk_lines_vg.vertex_buffer.read().unwrap();
+        let chunk_indices = chunk_lines_vg.index_buffer.read().unwrap();
+        let segments = if chunk_indices.len() < 2 {
+            None
+        } else {
+            Some(CpuAccessibleBufferXalloc::<[VertexPositionColorWidth]>::from_iter(
+                info.device.clone(),
+                BufferUsage::all(),
+                chunk_indices.iter().map(|&i| {
+                    let v = &chunk_vertices[i as usize];
+                    VertexPositionColorWidth { position: v.position, width: 0.0, color: v.color }
+                })
+            ).unwrap())
+        };
+        self.chunk_segments = Some((chunk_lines_vg.clone(), segments.clone()));
+        segments
     }
 }
 
@@ -70,39 +143,109 @@ impl RenderPipelineAbstract for LinesRenderPipeline {
     }
 
     fn build_command_buffer(&mut self, info: &RenderInfo) -> (AutoCommandBuffer, Arc<Queue>) {
-        let descriptor_set;
         let subbuffer = self.uniform_buffer_pool.next(LinesShaders::vertex::ty::Data {
             world: Matrix4::from_scale(1.0).into(),
             view: info.view_mat.into(),
-            proj: info.proj_mat.into(),
+            // lines are drawn after the TAA resolve, so they shouldn't be jittered
+            proj: info.unjittered_proj_mat.into(),
         }).unwrap();
-        descriptor_set = Arc::new(PersistentDescriptorSet::start(self.vulkan_pipeline.clone(), 0)
-            .add_buffer(subbuffer).unwrap()
-            .build().unwrap()
-        );
         let lock = info.render_queues.read().unwrap();
-        let cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family())
+        let dynamic_state = DynamicState {
+            line_width: None,
+            viewports: Some(vec![Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [info.dimensions[0] as f32, info.dimensions[1] as f32],
+                depth_range: 0.0..1.0,
+            }]),
+            scissors: None,
+            compare_mask: None,
+            write_mask: None,
+            reference: None
+        };
+        let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family())
             .unwrap()
             .begin_render_pass(
                 self.framebuffers.as_ref().unwrap()[info.image_num].clone(), false,
-                vec![ClearValue::None, ClearValue::None]).unwrap()
-            .draw_indexed(self.vulkan_pipeline.clone(), &DynamicState {
-                line_width: None,
-                viewports: Some(vec![Viewport {
-                    origin: [0.0, 0.0],
-                    dimensions: [info.dimensions[0] as f32, info.dimensions[1] as f32],
-                    depth_range: 0.0..1.0,
-                }]),
-                scissors: None,
-                compare_mask: None,
-                write_mask: None,
-                reference: None
+                vec![ClearValue::None, ClearValue::None]).unwrap();
+
+        match info.lines_info.mode {
+            LineRenderMode::Hardware => {
+                let descriptor_set = Arc::new(PersistentDescriptorSet::start(self.vulkan_pipeline.clone(), 0)
+                    .add_buffer(subbuffer).unwrap()
+                    .build().unwrap()
+                );
+                cb = cb.draw_indexed(self.vulkan_pipeline.clone(), &dynamic_state,
+                                     vec![lock.lines.chunk_lines_vg.vertex_buffer.clone()],
+                                     lock.lines.chunk_lines_vg.index_buffer.clone(),
+                                     descriptor_set.clone(), ()).unwrap();
+
+                // debug shapes are gathered into one transient buffer per depth mode
+                for &depth_test in [false, true].iter() {
+                    let vertices: Vec<VertexPositionColorWidth> = lock.debug.lines()
+                        .filter(|l| l.depth_test == depth_test)
+                        .flat_map(|l| l.vertices.iter().cloned())
+                        .collect();
+                    if vertices.is_empty() {
+                        continue;
+                    }
+                    let vertex_buffer = CpuAccessibleBufferXalloc::<[VertexPositionColorWidth]>::from_iter(
+                        info.device.clone(),
+                        BufferUsage::all(),
+                        vertices.into_iter()
+                    ).unwrap();
+                    cb = cb.draw(self.debug_pipelines[depth_test as usize].clone(), &dynamic_state,
+                                 vec![vertex_buffer],
+                                 descriptor_set.clone(), ()).unwrap();
+                }
             },
-                          vec![lock.lines.chunk_lines_vg.vertex_buffer.clone()],
-                          lock.lines.chunk_lines_vg.index_buffer.clone(),
-                          descriptor_set.clone(), ()).unwrap()
-            .end_render_pass().unwrap()
+            LineRenderMode::Wide => {
+                let constants = LinesShaders::wide_vertex::ty::Constants {
+                    screen_dimensions: [info.dimensions[0] as f32, info.dimensions[1] as f32],
+                    default_width: info.lines_info.width,
+                    feather: info.lines_info.feather,
+                    dash_length: info.lines_info.dash_length,
+                    gap_length: info.lines_info.gap_length,
+                };
+
+                // chunk lines are depth tested, and only read back when they change
+                let chunk_segments = self.chunk_segments(info, &lock.lines.chunk_lines_vg);
+                for &depth_test in [false, true].iter() {
+                    let vertices: Vec<VertexPositionColorWidth> = lock.debug.lines()
+                        .filter(|l| l.depth_test == depth_test)
+                        .flat_map(|l| l.vertices.iter().cloned())
+                        .collect();
+                    let mut segment_buffers = Vec::new();
+                    if !vertices.is_empty() {
+                        segment_buffers.push(CpuAccessibleBufferXalloc::<[VertexPositionColorWidth]>::from_iter(
+                            info.device.clone(),
+                            BufferUsage::all(),
+                            vertices.into_iter()
+                        ).unwrap());
+                    }
+                    if depth_test {
+                        segment_buffers.extend(chunk_segments.clone());
+                    }
+
+                    let pipeline = self.wide_pipelines[depth_test as usize].clone();
+                    for segment_buffer in segment_buffers.into_iter() {
+                        let segment_count = segment_buffer.len() / 2;
+                        if segment_count == 0 {
+                            continue;
+                        }
+                        let descriptor_set = PersistentDescriptorSet::start(pipeline.clone(), 0)
+                            .add_buffer(subbuffer.clone()).unwrap()
+                            .add_buffer(segment_buffer).unwrap()
+                            .build().unwrap();
+                        cb = cb.draw(pipeline.clone(), &dynamic_state,
+                                     BufferlessVertices { vertices: segment_count * 6, instances: 1 },
+                                     descriptor_set, constants.clone()).unwrap();
+                    }
+                }
+            }
+        }
+
+        let cb = cb.end_render_pass().unwrap()
             .build().unwrap();
         (cb, info.queue_main.clone())
     }
-}
\ No newline at end of file
+}
@@ -1,26 +1,29 @@
 //! Rendering pipeline types.
 
 pub mod occlusion;
+pub mod picking;
 pub mod deferred_shading;
 pub mod deferred_lighting;
 pub mod lines;
 pub mod text;
 pub mod postprocess;
+pub mod temporal_aa;
 pub mod imgui;
 pub use self::occlusion::OcclusionRenderPipeline;
+pub use self::picking::PickingRenderPipeline;
 pub use self::deferred_shading::DeferredShadingRenderPipeline;
 pub use self::deferred_lighting::DeferredLightingRenderPipeline;
 pub use self::lines::LinesRenderPipeline;
 pub use self::text::TextRenderPipeline;
 pub use self::postprocess::PostProcessRenderPipeline;
+pub use self::temporal_aa::TemporalAARenderPipeline;
 
 
 use std::sync::Arc;
 use vulkano::command_buffer::AutoCommandBuffer;
 use vulkano::device::Queue;
-use vulkano::image::swapchain::SwapchainImage;
+use vulkano::image::ImageViewAccess;
 use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract};
-use winit::Window;
 
 use crate::renderer::RenderInfo;
 
@@ -37,7 +40,7 @@ pub trait RenderPipelineAbstract {
 
     fn remove_framebuffers(&mut self) { *self.get_framebuffers_mut() = None; }
 
-    fn recreate_framebuffers_if_none(&mut self, images: &Vec<Arc<SwapchainImage<Window>>>, info: &RenderInfo) {
+    fn recreate_framebuffers_if_none(&mut self, images: &[Arc<dyn ImageViewAccess + Send + Sync>], info: &RenderInfo) {
         if self.get_framebuffers_mut().is_none() {
             let new_framebuffers = Some(images.iter().map(|image| {
                 let arc: Arc<dyn FramebufferAbstract + Send + Sync> = Arc::new(Framebuffer::start(self.get_renderpass().clone())
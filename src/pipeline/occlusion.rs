@@ -1,30 +1,124 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+use vulkano::buffer::BufferUsage;
 use vulkano::command_buffer::{AutoCommandBufferBuilder, AutoCommandBuffer, DynamicState};
-use vulkano::device::Queue;
+use vulkano::device::{Device, Queue};
 use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPass, RenderPassDesc, Subpass, RenderPassAbstract};
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
-use vulkano::image::{AttachmentImage, ImageUsage, SwapchainImage};
+use vulkano::image::{AttachmentImage, ImageUsage, ImageViewAccess};
 use vulkano::format::{R32Uint, D32Sfloat};
 
+use crate::buffer::CpuAccessibleBufferXalloc;
+use crate::readback::ReadbackRing;
 use crate::geometry::VertexPositionObjectId;
-use crate::renderer::{VULKAN_CORRECT_CLIP, RenderInfo};
+use crate::renderer::{RenderInfo, OcclusionCamera};
 use crate::renderpass::OcclusionRenderPass;
 use crate::shader::occlusion as OcclusionShaders;
 use crate::pipeline::RenderPipelineAbstract;
-use cgmath::Deg;
-use winit::Window;
+use cgmath::{Angle, Matrix4};
 
 
+/// Default `OcclusionInfo::resolution`.
 pub const OCCLUSION_FRAME_SIZE: [u32; 2] = [256, 144];
 
 
+/// Object visibility from one occlusion frame.
+///
+/// Object IDs come from `VertexPositionObjectId::id`. ID 0 is the cleared background and is never reported.
+#[derive(Clone, Debug, Default)]
+pub struct OcclusionResults {
+    /// Frame the results were rendered on, 0 if no occlusion frame has been read back yet.
+    pub frame_index: u64,
+    /// Resolution of the occlusion frame.
+    pub dimensions: [u32; 2],
+    /// Number of pixels of the occlusion frame covered by each visible object.
+    pub coverage: HashMap<u32, u32>,
+}
+
+
+impl OcclusionResults {
+    pub fn visible_ids(&self) -> HashSet<u32> {
+        self.coverage.keys().cloned().collect()
+    }
+
+
+    pub fn is_visible(&self, id: u32) -> bool {
+        self.coverage.contains_key(&id)
+    }
+
+
+    /// Fraction of the screen covered by an object, from 0.0 to 1.0.
+    pub fn screen_fraction(&self, id: u32) -> f32 {
+        let pixels = self.coverage.get(&id).cloned().unwrap_or(0);
+        pixels as f32 / (self.dimensions[0] * self.dimensions[1]).max(1) as f32
+    }
+}
+
+
+/// Copies occlusion frames back to the CPU without stalling, and counts the pixels of each object ID.
+///
+/// Recorded by `OcclusionRenderPipeline`, polled by the renderer every frame.
+pub struct OcclusionReadback {
+    buffers: ReadbackRing<Arc<CpuAccessibleBufferXalloc<[u32]>>, ()>,
+    dimensions: [u32; 2],
+    pub results: OcclusionResults,
+}
+
+
+impl OcclusionReadback {
+    /// Creates readback buffers for an occlusion frame of the given size.
+    pub fn new(device: Arc<Device>, dimensions: [u32; 2]) -> Self {
+        let pixels = (dimensions[0] * dimensions[1]) as usize;
+        let buffers = ReadbackRing::new(|| {
+            CpuAccessibleBufferXalloc::<[u32]>::from_iter(device.clone(), BufferUsage::transfer_destination(),
+                                                          vec![0u32; pixels].iter().cloned()).expect("failed to create buffer")
+        });
+
+        Self {
+            buffers,
+            dimensions,
+            results: OcclusionResults::default(),
+        }
+    }
+
+
+    /// Copies the ID image into the next free ring slot.
+    pub fn record(&mut self, cb: AutoCommandBufferBuilder, image: Arc<AttachmentImage<R32Uint>>, frame_index: u64) -> AutoCommandBufferBuilder {
+        match self.buffers.begin(frame_index, ()) {
+            Some(buffer) => cb.copy_image_to_buffer(image, buffer.clone()).unwrap(),
+            // every slot is still in flight, skip this frame rather than stall
+            None => cb
+        }
+    }
+
+
+    /// Reads back any finished frames, keeping the results of the newest one.
+    pub fn poll(&mut self) {
+        let newest = self.buffers.poll(|buffer, _| {
+            let ids = buffer.read().ok()?;
+            let mut coverage = HashMap::new();
+            for &id in ids.iter().filter(|&&id| id != 0) {
+                *coverage.entry(id).or_insert(0u32) += 1;
+            }
+            Some(coverage)
+        });
+        if let Some((frame_index, _, coverage)) = newest {
+            self.results = OcclusionResults { frame_index, dimensions: self.dimensions, coverage };
+        }
+    }
+}
+
+
 pub struct OcclusionRenderPipeline {
     vulkan_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
     framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
     renderpass: Arc<RenderPass<OcclusionRenderPass>>,
     color_attachment: Arc<AttachmentImage<R32Uint>>,
+    /// Read by `HiZCompute` to build the Hi-Z pyramid.
+    depth_buffer: Arc<AttachmentImage<D32Sfloat>>,
+    dimensions: [u32; 2],
     dummy_fb: Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>,
 }
 
@@ -47,7 +141,7 @@ impl OcclusionRenderPipeline {
             .triangle_list()
             .viewports_dynamic_scissors_irrelevant(1)
             .fragment_shader(fs.main_entry_point(), ())
-            .depth_stencil_simple_depth()
+            .depth_stencil(info.depth_mode.depth_test())
             .render_pass(Subpass::from(renderpass.clone(), 0).unwrap())
             .build(info.device.clone())
             .unwrap());
@@ -56,6 +150,7 @@ impl OcclusionRenderPipeline {
                                                            ImageUsage {
                                                                transfer_source: true,
                                                                depth_stencil_attachment: true,
+                                                               sampled: true,
                                                                ..ImageUsage::none()
                                                            }).unwrap();
         let color_attachment = AttachmentImage::with_usage(info.device.clone(), dimensions, R32Uint,
@@ -77,6 +172,8 @@ impl OcclusionRenderPipeline {
             framebuffer,
             renderpass,
             color_attachment,
+            depth_buffer,
+            dimensions,
             dummy_fb: None
         }
     }
@@ -91,19 +188,19 @@ impl RenderPipelineAbstract for OcclusionRenderPipeline {
     }
 
     fn build_command_buffer(&mut self, info: &RenderInfo) -> (AutoCommandBuffer, Arc<Queue>) {
-        let proj = VULKAN_CORRECT_CLIP * cgmath::perspective(Deg(75f32), (OCCLUSION_FRAME_SIZE[0] as f32) / (OCCLUSION_FRAME_SIZE[1] as f32), 0.1, 100.0);
+        let proj = occlusion_projection(info);
         let lock = info.render_queues.read().unwrap();
 
         let cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_offscreen.family())
             .unwrap()
             .begin_render_pass(
                 self.framebuffer.clone(), false,
-                vec![[0u32].into(), 1f32.into()]).unwrap()
+                vec![[0u32].into(), info.depth_mode.clear_depth().into()]).unwrap()
             .draw_indexed(self.vulkan_pipeline.clone(), &DynamicState {
                 line_width: None,
                 viewports: Some(vec![Viewport {
                     origin: [0.0, 0.0],
-                    dimensions: [OCCLUSION_FRAME_SIZE[0] as f32, OCCLUSION_FRAME_SIZE[1] as f32],
+                    dimensions: [self.dimensions[0] as f32, self.dimensions[1] as f32],
                     depth_range: 0.0..1.0,
                 }]),
                 scissors: None,
@@ -117,13 +214,27 @@ impl RenderPipelineAbstract for OcclusionRenderPipeline {
                                 view: info.view_mat.into(),
                                 proj: proj.into(),
                           }).unwrap()
-            .end_render_pass().unwrap()
-            .copy_image_to_buffer(self.color_attachment.clone(), lock.occluders.output_cpu_buffer.clone()).unwrap()
+            .end_render_pass().unwrap();
+        let cb = info.occlusion_readback.lock().record(cb, self.color_attachment.clone(), info.frame_index);
+        let cb = info.hiz_compute.lock().record(cb, self.depth_buffer.clone(), proj * info.view_mat, info.frame_index)
             .build().unwrap();
         (cb, info.queue_offscreen.clone())
     }
 
-    fn recreate_framebuffers_if_none(&mut self, _: &Vec<Arc<SwapchainImage<Window>>>, _: &RenderInfo) {
+    fn recreate_framebuffers_if_none(&mut self, _: &[Arc<dyn ImageViewAccess + Send + Sync>], _: &RenderInfo) {
         // OcclusionRenderPipeline uses a fixed offscreen framebuffer
     }
-}
\ No newline at end of file
+}
+
+
+/// Projection for the occlusion pass, derived from the main camera's unjittered projection so near
+/// and far planes and aspect ratio always match it.
+fn occlusion_projection(info: &RenderInfo) -> Matrix4<f32> {
+    let scale = match info.occlusion_info.camera {
+        OcclusionCamera::MatchCamera => 1.0,
+        // proj.y.y is 1 / tan(fov / 2) of the main camera
+        OcclusionCamera::Fov(fov) => 1.0 / (info.unjittered_proj_mat.y.y.abs() * (fov / 2.0).tan()),
+        OcclusionCamera::GuardBand(band) => 1.0 / (1.0 + band.max(0.0)),
+    };
+    Matrix4::from_nonuniform_scale(scale, scale, 1.0) * info.unjittered_proj_mat
+}
@@ -0,0 +1,283 @@
+//! Mouse picking.
+//!
+//! Meshes with a nonzero `MeshRenderQueueEntry::object_id` are drawn into full resolution object ID,
+//! triangle ID and world position targets, on frames where a pick has been requested. A small region
+//! around the requested pixel is copied back to the CPU and read a few frames later, without stalling.
+
+use std::sync::Arc;
+
+use cgmath::Point3;
+use vulkano::buffer::BufferUsage;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, AutoCommandBuffer, DynamicState};
+use vulkano::device::{Device, Queue};
+use vulkano::format::{R32Uint, R32G32B32A32Sfloat, D32Sfloat};
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPass, RenderPassDesc, Subpass, RenderPassAbstract};
+use vulkano::image::{AttachmentImage, ImageUsage, ImageViewAccess};
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+
+use crate::buffer::CpuAccessibleBufferXalloc;
+use crate::geometry::DeferredShadingVertex;
+use crate::pipeline::RenderPipelineAbstract;
+use crate::readback::ReadbackRing;
+use crate::renderer::RenderInfo;
+use crate::renderpass::PickingRenderPass;
+use crate::shader::picking as PickingShaders;
+
+
+/// Width and height of the region read back around a picked pixel, so thin objects are easier to hit.
+pub const PICK_REGION_SIZE: u32 = 5;
+
+
+/// What's under a picked pixel.
+#[derive(Clone, Debug)]
+pub struct PickResult {
+    /// `MeshRenderQueueEntry::object_id` of the mesh.
+    pub object_id: u32,
+    /// Index of the triangle in the mesh, i.e. the position of its first index in the index buffer divided by 3.
+    pub triangle_id: u32,
+    /// World space position of the surface.
+    pub position: Point3<f32>,
+    /// Frame the pick was rendered on.
+    pub frame_index: u64,
+}
+
+
+/// CPU buffers for one in-flight pick.
+struct PickSlot {
+    object_ids: Arc<CpuAccessibleBufferXalloc<[u32]>>,
+    triangle_ids: Arc<CpuAccessibleBufferXalloc<[u32]>>,
+    positions: Arc<CpuAccessibleBufferXalloc<[[f32; 4]]>>,
+}
+
+
+/// Requested pixel, copied region size and offset of the requested pixel inside the region.
+type PickRequest = ([u32; 2], [u32; 2], [u32; 2]);
+
+
+/// Render targets at the current screen size.
+struct PickingTargets {
+    object_id: Arc<AttachmentImage<R32Uint>>,
+    triangle_id: Arc<AttachmentImage<R32Uint>>,
+    position: Arc<AttachmentImage<R32G32B32A32Sfloat>>,
+    dimensions: [u32; 2],
+}
+
+
+pub struct PickingRenderPipeline {
+    vulkan_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    framebuffers: Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>,
+    renderpass: Arc<RenderPass<PickingRenderPass>>,
+    targets: Option<PickingTargets>,
+    slots: ReadbackRing<PickSlot, PickRequest>,
+    /// Pixel to pick on the next frame.
+    requested: Option<[u32; 2]>,
+    /// Pixel and result of the newest pick read back so far.
+    latest: Option<([u32; 2], Option<PickResult>)>,
+}
+
+
+impl PickingRenderPipeline {
+    pub fn new(info: &RenderInfo) -> Self {
+        let vs = PickingShaders::vertex::Shader::load(info.device.clone()).expect("failed to create shader module");
+        let fs = PickingShaders::fragment::Shader::load(info.device.clone()).expect("failed to create shader module");
+
+        let renderpass = Arc::new(
+            PickingRenderPass {}
+                .build_render_pass(info.device.clone())
+                .unwrap()
+        );
+
+        let pipeline = Arc::new(GraphicsPipeline::start()
+            .cull_mode_back()
+            .vertex_input_single_buffer::<DeferredShadingVertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .depth_stencil(info.depth_mode.depth_test())
+            .render_pass(Subpass::from(renderpass.clone(), 0).unwrap())
+            .build(info.device.clone())
+            .unwrap());
+
+        let slots = ReadbackRing::new(|| PickSlot::new(info.device.clone()));
+
+        PickingRenderPipeline {
+            vulkan_pipeline: pipeline,
+            framebuffers: None,
+            renderpass,
+            targets: None,
+            slots,
+            requested: None,
+            latest: None,
+        }
+    }
+
+
+    /// Requests a pick at a pixel of the window and returns the newest result read back for that pixel.
+    ///
+    /// Results arrive a few frames after the first request, so call this every frame the pixel is of
+    /// interest (e.g. with the mouse position). Returns `None` until then, or if there's nothing pickable there.
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<PickResult> {
+        self.poll();
+        self.requested = Some([x, y]);
+        match &self.latest {
+            Some((pixel, result)) if *pixel == [x, y] => result.clone(),
+            _ => None
+        }
+    }
+
+
+    /// True if the pass needs to run this frame.
+    pub fn has_request(&self) -> bool {
+        self.requested.is_some()
+    }
+
+
+    /// Reads back any finished picks, keeping the result of the newest one.
+    pub fn poll(&mut self) {
+        let newest = self.slots.poll(|slot, &(_, region, center)| slot.read(region, center).ok());
+        if let Some((frame_index, (pixel, _, _), hit)) = newest {
+            let result = hit.map(|(object_id, triangle_id, position)| PickResult { object_id, triangle_id, position, frame_index });
+            self.latest = Some((pixel, result));
+        }
+    }
+}
+
+
+impl PickSlot {
+    fn new(device: Arc<Device>) -> Self {
+        let texels = (PICK_REGION_SIZE * PICK_REGION_SIZE) as usize;
+        PickSlot {
+            object_ids: CpuAccessibleBufferXalloc::<[u32]>::from_iter(device.clone(), BufferUsage::transfer_destination(),
+                                                                      vec![0u32; texels].iter().cloned()).expect("failed to create buffer"),
+            triangle_ids: CpuAccessibleBufferXalloc::<[u32]>::from_iter(device.clone(), BufferUsage::transfer_destination(),
+                                                                        vec![0u32; texels].iter().cloned()).expect("failed to create buffer"),
+            positions: CpuAccessibleBufferXalloc::<[[f32; 4]]>::from_iter(device, BufferUsage::transfer_destination(),
+                                                                          vec![[0f32; 4]; texels].iter().cloned()).expect("failed to create buffer"),
+        }
+    }
+
+
+    /// Picks the hit texel closest to `center`, returning its object ID, triangle ID and position. Errors if
+    /// the GPU is still writing the buffers.
+    fn read(&self, region: [u32; 2], center: [u32; 2]) -> Result<Option<(u32, u32, Point3<f32>)>, ()> {
+        let object_ids = self.object_ids.read().map_err(|_| ())?;
+        let triangle_ids = self.triangle_ids.read().map_err(|_| ())?;
+        let positions = self.positions.read().map_err(|_| ())?;
+
+        let mut closest: Option<(u32, usize)> = None;
+        for y in 0..region[1] {
+            for x in 0..region[0] {
+                let i = (y * region[0] + x) as usize;
+                if object_ids[i] == 0 {
+                    continue;
+                }
+                let dx = x as i32 - center[0] as i32;
+                let dy = y as i32 - center[1] as i32;
+                let distance = (dx * dx + dy * dy) as u32;
+                if closest.map_or(true, |(d, _)| distance < d) {
+                    closest = Some((distance, i));
+                }
+            }
+        }
+
+        Ok(closest.map(|(_, i)| (object_ids[i], triangle_ids[i], Point3::new(positions[i][0], positions[i][1], positions[i][2]))))
+    }
+}
+
+
+impl RenderPipelineAbstract for PickingRenderPipeline {
+    fn get_framebuffers_mut(&mut self) -> &mut Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>> {
+        &mut self.framebuffers
+    }
+
+    fn get_renderpass(&self) -> Arc<dyn RenderPassAbstract + Send + Sync> {
+        self.renderpass.clone() as Arc<dyn RenderPassAbstract + Send + Sync>
+    }
+
+    fn build_command_buffer(&mut self, info: &RenderInfo) -> (AutoCommandBuffer, Arc<Queue>) {
+        let lock = info.render_queues.read().unwrap();
+        // unjittered, so picks don't flicker between frames
+        let view_proj = info.unjittered_proj_mat * info.view_mat;
+        let dimensions = self.targets.as_ref().unwrap().dimensions;
+
+        let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family())
+            .unwrap()
+            .begin_render_pass(self.framebuffers.as_ref().unwrap()[0].clone(), false,
+                               vec![[0u32].into(), [0u32].into(), [0f32; 4].into(), info.depth_mode.clear_depth().into()]).unwrap();
+
+        for entry in lock.meshes.iter().filter(|e| e.object_id != 0) {
+            cb = cb.draw_indexed(self.vulkan_pipeline.clone(), &DynamicState {
+                line_width: None,
+                viewports: Some(vec![Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                    depth_range: 0.0..1.0,
+                }]),
+                scissors: None,
+                compare_mask: None,
+                write_mask: None,
+                reference: None
+            },
+                                 vec![entry.vertex_group.vertex_buffer.clone()],
+                                 entry.vertex_group.index_buffer.clone(),
+                                 (), PickingShaders::vertex::ty::Constants {
+                                     world: entry.transform.into(),
+                                     view_proj: view_proj.into(),
+                                     object_id: entry.object_id,
+                                 }).unwrap();
+        }
+        cb = cb.end_render_pass().unwrap();
+
+        if let Some(pixel) = self.requested {
+            // clamp the region to the screen, keeping the requested pixel inside it
+            let x = pixel[0].min(dimensions[0] - 1);
+            let y = pixel[1].min(dimensions[1] - 1);
+            let size = [PICK_REGION_SIZE.min(dimensions[0]), PICK_REGION_SIZE.min(dimensions[1])];
+            let offset = [x.saturating_sub(PICK_REGION_SIZE / 2).min(dimensions[0] - size[0]),
+                          y.saturating_sub(PICK_REGION_SIZE / 2).min(dimensions[1] - size[1])];
+
+            // if every slot is still in flight, keep the request for next frame
+            if let Some(slot) = self.slots.begin(info.frame_index, (pixel, size, [x - offset[0], y - offset[1]])) {
+                self.requested = None;
+                let targets = self.targets.as_ref().unwrap();
+                cb = cb.copy_image_to_buffer_dimensions(targets.object_id.clone(), slot.object_ids.clone(),
+                                                        [offset[0], offset[1], 0], [size[0], size[1], 1], 0, 1, 0).unwrap()
+                    .copy_image_to_buffer_dimensions(targets.triangle_id.clone(), slot.triangle_ids.clone(),
+                                                     [offset[0], offset[1], 0], [size[0], size[1], 1], 0, 1, 0).unwrap()
+                    .copy_image_to_buffer_dimensions(targets.position.clone(), slot.positions.clone(),
+                                                     [offset[0], offset[1], 0], [size[0], size[1], 1], 0, 1, 0).unwrap();
+            }
+        }
+
+        (cb.build().unwrap(), info.queue_main.clone())
+    }
+
+    fn recreate_framebuffers_if_none(&mut self, _: &[Arc<dyn ImageViewAccess + Send + Sync>], info: &RenderInfo) {
+        if self.framebuffers.is_some() {
+            return;
+        }
+        let color_usage = ImageUsage {
+            color_attachment: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+        let targets = PickingTargets {
+            object_id: AttachmentImage::with_usage(info.device.clone(), info.dimensions, R32Uint, color_usage).unwrap(),
+            triangle_id: AttachmentImage::with_usage(info.device.clone(), info.dimensions, R32Uint, color_usage).unwrap(),
+            position: AttachmentImage::with_usage(info.device.clone(), info.dimensions, R32G32B32A32Sfloat, color_usage).unwrap(),
+            dimensions: info.dimensions,
+        };
+        let depth = AttachmentImage::transient(info.device.clone(), info.dimensions, D32Sfloat).unwrap();
+
+        let framebuffer: Arc<dyn FramebufferAbstract + Send + Sync> = Arc::new(Framebuffer::start(self.renderpass.clone())
+            .add(targets.object_id.clone()).unwrap()
+            .add(targets.triangle_id.clone()).unwrap()
+            .add(targets.position.clone()).unwrap()
+            .add(depth).unwrap()
+            .build().unwrap());
+        self.framebuffers = Some(vec![framebuffer]);
+        self.targets = Some(targets);
+    }
+}
@@ -1,14 +1,14 @@
 use std::sync::Arc;
 
 use vulkano::buffer::BufferUsage;
-use vulkano::command_buffer::{AutoCommandBufferBuilder, AutoCommandBuffer, DynamicState, BlitImageError};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, AutoCommandBuffer, DynamicState};
 use vulkano::device::Queue;
 use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPass, RenderPassDesc, Subpass, RenderPassAbstract};
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
 use vulkano::format::ClearValue;
-use vulkano::image::SwapchainImage;
+use vulkano::image::ImageViewAccess;
 
 use crate::geometry::VertexPosition;
 use crate::renderer::RenderInfo;
@@ -16,9 +16,7 @@ use crate::renderpass::PostProcessRenderPass;
 use crate::shader::tonemapper as TonemapperShaders;
 use crate::pipeline::RenderPipelineAbstract;
 use crate::buffer::CpuAccessibleBufferXalloc;
-use winit::Window;
 use vulkano::sampler::{Sampler, Filter, SamplerAddressMode, MipmapMode};
-use vulkano::command_buffer::validity::CheckBlitImageError;
 
 
 pub struct PostProcessRenderPipeline {
@@ -27,6 +25,8 @@ pub struct PostProcessRenderPipeline {
     renderpass: Arc<RenderPass<PostProcessRenderPass>>,
     fullscreen_vertex_buffer: Arc<CpuAccessibleBufferXalloc<[VertexPosition]>>,
     occlusion_buf_sampler: Arc<Sampler>,
+    screen_sampler: Arc<Sampler>,
+    lut_sampler: Arc<Sampler>,
 }
 
 
@@ -67,13 +67,21 @@ impl PostProcessRenderPipeline {
         let occlusion_buf_sampler = Sampler::new(info.device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Linear,
                                                  SamplerAddressMode::Repeat, SamplerAddressMode::Repeat, SamplerAddressMode::Repeat,
                                                  0.0, 4.0, 0.0, 0.0).unwrap();
+        let screen_sampler = Sampler::new(info.device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Nearest,
+                                          SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                          0.0, 1.0, 0.0, 0.0).unwrap();
+        let lut_sampler = Sampler::new(info.device.clone(), Filter::Linear, Filter::Linear, MipmapMode::Nearest,
+                                       SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                       0.0, 1.0, 0.0, 0.0).unwrap();
 
         PostProcessRenderPipeline {
             pipeline,
             framebuffers: None,
             renderpass,
             fullscreen_vertex_buffer,
-            occlusion_buf_sampler
+            occlusion_buf_sampler,
+            screen_sampler,
+            lut_sampler
         }
     }
 }
@@ -90,6 +98,11 @@ impl RenderPipelineAbstract for PostProcessRenderPipeline {
     }
 
     fn build_command_buffer(&mut self, info: &RenderInfo) -> (AutoCommandBuffer, Arc<Queue>) {
+        let grading = &info.color_grading_info;
+        // fall back to the neutral LUT if a name isn't registered (yet)
+        let lut_a = info.tex_registry.get_lut(&grading.lut_a).unwrap_or_else(|| info.tex_registry.get_lut("neutral").unwrap());
+        let lut_b = info.tex_registry.get_lut(&grading.lut_b).unwrap_or_else(|| info.tex_registry.get_lut("neutral").unwrap());
+
         let descriptor_set = Arc::new(PersistentDescriptorSet::start(self.pipeline.clone(), 0)
             .add_image(info.attachments.position.clone()).unwrap()
             .add_image(info.attachments.normal.clone()).unwrap()
@@ -99,13 +112,18 @@ impl RenderPipelineAbstract for PostProcessRenderPipeline {
             .add_image(info.attachments.hdr_diffuse.clone()).unwrap()
             .add_image(info.attachments.hdr_specular.clone()).unwrap()
             .add_sampled_image(info.attachments.occlusion.as_ref().unwrap().clone(), self.occlusion_buf_sampler.clone()).unwrap()
+            .add_sampled_image(info.attachments.taa_history[(info.frame_index % 2) as usize].clone(), self.screen_sampler.clone()).unwrap()
+            .add_sampled_image(info.attachments.velocity.clone(), self.screen_sampler.clone()).unwrap()
+            .add_sampled_image(info.attachments.ao.clone(), self.screen_sampler.clone()).unwrap()
+            .add_sampled_image(lut_a, self.lut_sampler.clone()).unwrap()
+            .add_sampled_image(lut_b, self.lut_sampler.clone()).unwrap()
             .build().unwrap()
         );
 
         let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family()).unwrap()
             .begin_render_pass(
                 self.framebuffers.as_ref().unwrap()[info.image_num].clone(), false,
-                vec![ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, [0.0, 0.0, 0.0, 1.0].into(), [0.0, 0.0, 0.0, 1.0].into(), [0,0,0,0].into() ]).unwrap();
+                vec![ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, ClearValue::None, [0.0, 0.0, 0.0, 1.0].into(), [0.0, 0.0, 0.0, 1.0].into(), [0.0, 0.0, 0.0, 0.0].into() ]).unwrap();
 
         cb = cb.draw(self.pipeline.clone(), &DynamicState {
             line_width: None,
@@ -122,36 +140,20 @@ impl RenderPipelineAbstract for PostProcessRenderPipeline {
                              vec![self.fullscreen_vertex_buffer.clone()],
                              descriptor_set, TonemapperShaders::fragment::ty::Constants {
                                 debug_vis_mode: info.debug_visualize_setting,
-                                _dummy0: [0u8; 4],
+                                tonemap_operator: info.tonemapping_info.operator as u32,
                                 screen_dimensions: [info.dimensions[0] as f32, info.dimensions[1] as f32],
-                                exposure_adjustment: info.tonemapping_info.exposure,
+                                exposure: info.tonemapping_info.exposure,
+                                white_point: info.tonemapping_info.white_point,
                                 vignette_opacity: info.tonemapping_info.vignette_opacity,
+                                color_grading_enabled: grading.enabled as u32,
+                                lut_blend: grading.blend,
                             }).unwrap()
             .end_render_pass().unwrap();
-        cb = match cb.blit_image(info.attachments.luma_render.clone(), [0, 0, 0], [info.dimensions[0] as i32, info.dimensions[1] as i32, 1], 0, 0,
-                                 info.attachments.luma_mips.clone(),   [0, 0, 0], [512, 512, 1], 0, 0, 1, Filter::Linear) {
-            Ok(c) => c,
-            Err(e) => {
-                match e {
-                    BlitImageError::CheckBlitImageError(ce) => {
-                        match ce {
-                            CheckBlitImageError::SourceCoordinatesOutOfRange => {
-                                warn!(Renderer, "Failed to build post process command buffer (luma blit stage): CheckBlitImageError(SourceCoordinatesOutOfRange)");
-                                return (AutoCommandBufferBuilder::new(info.device.clone(), info.queue_main.family()).unwrap().build().unwrap(), info.queue_main.clone());
-                            },
-                            _ => { fatal!(Renderer, "{:?}", e); }
-                        }
-                    },
-                    _ => { fatal!(Renderer, "{:?}", e); }
-                }
-            }
-        };
-
-        cb = cb.copy_image_to_buffer_dimensions(info.attachments.luma_mips.clone(), info.histogram_compute.lock().source_buffer.clone(), [0, 0, 0], [512, 512, 1], 0, 1, 0).unwrap();
+        cb = info.histogram_compute.lock().record(cb, info);
         (cb.build().unwrap(), info.queue_main.clone())
     }
 
-    fn recreate_framebuffers_if_none(&mut self, images: &Vec<Arc<SwapchainImage<Window>>>, info: &RenderInfo) {
+    fn recreate_framebuffers_if_none(&mut self, images: &[Arc<dyn ImageViewAccess + Send + Sync>], info: &RenderInfo) {
         if self.get_framebuffers_mut().is_none() {
             let new_framebuffers = Some(images.iter().map(|image| {
                 let arc: Arc<dyn FramebufferAbstract + Send + Sync> = Arc::new(Framebuffer::start(self.get_renderpass().clone())
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use vulkano::buffer::BufferUsage;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, AutoCommandBuffer, DynamicState};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Queue;
+use vulkano::format::ClearValue;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPass, RenderPassDesc, Subpass, RenderPassAbstract};
+use vulkano::image::ImageViewAccess;
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::sampler::{Sampler, Filter, SamplerAddressMode, MipmapMode};
+
+use crate::geometry::VertexPosition;
+use crate::pipeline::RenderPipelineAbstract;
+use crate::renderer::RenderInfo;
+use crate::renderpass::TemporalAARenderPass;
+use crate::shader::temporal_aa as TemporalAAShaders;
+use crate::buffer::CpuAccessibleBufferXalloc;
+
+
+/// Number of samples in the jitter sequence before it repeats.
+pub const TAA_JITTER_SEQUENCE_LENGTH: u32 = 8;
+
+
+/// Returns the `index`th element of the Halton sequence with the given base, in the range [0, 1).
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut f = 1.0;
+    while index > 0 {
+        f /= base as f32;
+        result += f * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+
+/// Resolves the lit HDR image against the reprojected history from the previous frame.
+///
+/// Writes into `taa_history[frame_index % 2]`, reading from the other history buffer.
+pub struct TemporalAARenderPipeline {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// One framebuffer per history buffer, *not* per swapchain image.
+    pub framebuffers: Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>,
+    renderpass: Arc<RenderPass<TemporalAARenderPass>>,
+    fullscreen_vertex_buffer: Arc<CpuAccessibleBufferXalloc<[VertexPosition]>>,
+    nearest_sampler: Arc<Sampler>,
+    linear_sampler: Arc<Sampler>,
+    /// False until a frame has been resolved into the current history buffers.
+    history_valid: bool,
+}
+
+
+impl TemporalAARenderPipeline {
+    pub fn new(info: &RenderInfo) -> Self {
+        let renderpass = Arc::new(
+            TemporalAARenderPass {}
+                .build_render_pass(info.device.clone())
+                .unwrap()
+        );
+
+        let pipeline = {
+            let vs = TemporalAAShaders::vertex::Shader::load(info.device.clone()).expect("failed to create shader module");
+            let fs = TemporalAAShaders::fragment::Shader::load(info.device.clone()).expect("failed to create shader module");
+
+            Arc::new(GraphicsPipeline::start()
+                .vertex_input_single_buffer::<VertexPosition>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .render_pass(Subpass::from(renderpass.clone(), 0).unwrap())
+                .build(info.device.clone())
+                .unwrap())
+        };
+
+        let fullscreen_vertex_buffer = CpuAccessibleBufferXalloc::<[VertexPosition]>::from_iter(
+            info.device.clone(), BufferUsage::all(), vec![
+                VertexPosition { position: [ -1.0,  1.0, 1.0 ] },
+                VertexPosition { position: [  1.0,  1.0, 1.0 ] },
+                VertexPosition { position: [  1.0, -1.0, 1.0 ] },
+                VertexPosition { position: [ -1.0,  1.0, 1.0 ] },
+                VertexPosition { position: [  1.0, -1.0, 1.0 ] },
+                VertexPosition { position: [ -1.0, -1.0, 1.0 ] },
+            ].iter().cloned()).expect("failed to create buffer");
+
+        let nearest_sampler = Sampler::new(info.device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Nearest,
+                                           SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                           0.0, 1.0, 0.0, 0.0).unwrap();
+        let linear_sampler = Sampler::new(info.device.clone(), Filter::Linear, Filter::Linear, MipmapMode::Nearest,
+                                          SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                          0.0, 1.0, 0.0, 0.0).unwrap();
+
+        TemporalAARenderPipeline {
+            pipeline,
+            framebuffers: None,
+            renderpass,
+            fullscreen_vertex_buffer,
+            nearest_sampler,
+            linear_sampler,
+            history_valid: false,
+        }
+    }
+}
+
+
+impl RenderPipelineAbstract for TemporalAARenderPipeline {
+    fn get_framebuffers_mut(&mut self) -> &mut Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>> {
+        &mut self.framebuffers
+    }
+
+
+    fn get_renderpass(&self) -> Arc<dyn RenderPassAbstract + Send + Sync> {
+        self.renderpass.clone() as Arc<dyn RenderPassAbstract + Send + Sync>
+    }
+
+    fn build_command_buffer(&mut self, info: &RenderInfo) -> (AutoCommandBuffer, Arc<Queue>) {
+        let current = (info.frame_index % 2) as usize;
+        let previous = 1 - current;
+
+        let descriptor_set = Arc::new(PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+            .add_sampled_image(info.attachments.hdr_diffuse.clone(), self.nearest_sampler.clone()).unwrap()
+            .add_sampled_image(info.attachments.hdr_specular.clone(), self.nearest_sampler.clone()).unwrap()
+            .add_sampled_image(info.attachments.velocity.clone(), self.nearest_sampler.clone()).unwrap()
+            .add_sampled_image(info.attachments.taa_history[previous].clone(), self.linear_sampler.clone()).unwrap()
+            .build().unwrap()
+        );
+
+        let cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family())
+            .unwrap()
+            .begin_render_pass(
+                self.framebuffers.as_ref().unwrap()[current].clone(), false,
+                vec![ClearValue::None]).unwrap()
+            .draw(self.pipeline.clone(), &DynamicState {
+                line_width: None,
+                viewports: Some(vec![Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [info.dimensions[0] as f32, info.dimensions[1] as f32],
+                    depth_range: 0.0..1.0,
+                }]),
+                scissors: None,
+                compare_mask: None,
+                write_mask: None,
+                reference: None
+            },
+                  vec![self.fullscreen_vertex_buffer.clone()],
+                  descriptor_set, TemporalAAShaders::fragment::ty::Constants {
+                    screen_dimensions: [info.dimensions[0] as f32, info.dimensions[1] as f32],
+                    history_weight: info.taa_info.history_weight,
+                    history_valid: self.history_valid as u32,
+                    enabled: info.taa_info.enabled as u32,
+                }).unwrap()
+            .end_render_pass().unwrap()
+            .build().unwrap();
+
+        self.history_valid = true;
+
+        (cb, info.queue_main.clone())
+    }
+
+    fn remove_framebuffers(&mut self) {
+        self.framebuffers = None;
+        // attachments are recreated along with framebuffers, so the history is gone
+        self.history_valid = false;
+    }
+
+    fn recreate_framebuffers_if_none(&mut self, _images: &[Arc<dyn ImageViewAccess + Send + Sync>], info: &RenderInfo) {
+        if self.get_framebuffers_mut().is_none() {
+            let new_framebuffers = Some(info.attachments.taa_history.iter().map(|history| {
+                let arc: Arc<dyn FramebufferAbstract + Send + Sync> = Arc::new(Framebuffer::start(self.get_renderpass().clone())
+                    .add(history.clone()).unwrap()
+                    .build().unwrap());
+                arc
+            }).collect::<Vec<_>>());
+            ::std::mem::replace(self.get_framebuffers_mut(), new_framebuffers);
+        }
+    }
+}
@@ -0,0 +1,428 @@
+//! Paragraph layout: line breaking, word wrapping, alignment and measurement.
+//!
+//! Each paragraph is first turned into a run of glyphs in logical order, either char by char with
+//! pair kerning, or by the shaper when the `shaping` feature is enabled. Lines are broken on that
+//! run, then reordered for display and positioned.
+
+
+use std::ops::Range;
+
+use rusttype::{Glyph, GlyphId, PositionedGlyph, Scale, VMetrics, point};
+
+use super::{FontData, TextData};
+use super::markup::StyledText;
+
+
+/// Horizontal alignment of each line within the text's `max_width`, or within the widest line if
+/// the text doesn't wrap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+    /// Stretches spaces so lines fill the width. The last line of each paragraph is left aligned.
+    Justify,
+}
+
+
+/// Extents of laid out text in pixels, relative to the text origin (top left of the first line).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextBounds {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+    pub line_count: usize,
+}
+
+impl TextBounds {
+    pub fn width(&self) -> f32 { self.max.0 - self.min.0 }
+    pub fn height(&self) -> f32 { self.max.1 - self.min.1 }
+}
+
+
+/// A laid out glyph, and the index of the font it came from.
+pub(super) struct LayoutGlyph {
+    pub font: usize,
+    pub glyph: PositionedGlyph<'static>,
+    /// Index in `StyledText::styles`.
+    pub style: usize,
+}
+
+
+/// A laid out inline icon. Positions are in pixels relative to the text origin.
+pub(super) struct LayoutIcon {
+    /// Index in `StyledText::spans`.
+    pub span: usize,
+    pub style: usize,
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+
+pub(super) struct Layout {
+    pub glyphs: Vec<LayoutGlyph>,
+    pub icons: Vec<LayoutIcon>,
+    pub bounds: TextBounds,
+}
+
+
+/// A glyph before line breaking. Advances and offsets are in pixels, offsets are y-down.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct ShapedGlyph {
+    pub font: usize,
+    pub id: GlyphId,
+    /// Byte index in the paragraph of the first char this glyph was made from.
+    pub cluster: usize,
+    pub advance: f32,
+    pub offset: (f32, f32),
+    pub is_space: bool,
+    /// Bidi embedding level, odd for right-to-left. Always 0 without shaping.
+    pub level: u8,
+    pub style: usize,
+    /// Index in `StyledText::spans`, if this is an icon rather than a glyph.
+    pub icon: Option<usize>,
+}
+
+
+/// Part of a paragraph with a single style, to be shaped with one font chain.
+pub(super) struct StyleRun<'a> {
+    /// Byte range in the paragraph.
+    pub range: Range<usize>,
+    pub chain: &'a [usize],
+    pub scale: Scale,
+    pub style: usize,
+    /// Index in `StyledText::spans`, if this run is an icon.
+    pub icon: Option<usize>,
+}
+
+
+struct Line {
+    /// In visual order, without trailing spaces.
+    glyphs: Vec<ShapedGlyph>,
+    width: f32,
+    last_in_paragraph: bool,
+    /// Style used for the metrics of empty lines.
+    empty_style: usize,
+}
+
+
+/// Picks the first font in `chain` that has a glyph for `c`, or the primary font's missing glyph.
+fn select_glyph(fonts: &[FontData], chain: &[usize], c: char) -> (usize, Glyph<'static>) {
+    for &index in chain.iter() {
+        let glyph = fonts[index].font.glyph(c);
+        if glyph.id().0 != 0 {
+            return (index, glyph);
+        }
+    }
+    (chain[0], fonts[chain[0]].font.glyph(c))
+}
+
+
+/// Makes the placeholder glyph for an icon run. Icons are square, with the size of the run's text.
+pub(super) fn icon_glyph(run: &StyleRun, level: u8) -> ShapedGlyph {
+    ShapedGlyph {
+        font: run.chain[0],
+        id: GlyphId(0),
+        cluster: run.range.start,
+        advance: run.scale.y,
+        offset: (0.0, 0.0),
+        is_space: false,
+        level,
+        style: run.style,
+        icon: run.icon,
+    }
+}
+
+
+/// Maps each char to a glyph from the first font in its run's chain that has it, with pair kerning.
+pub(super) fn shape_simple(fonts: &[FontData], paragraph: &str, runs: &[StyleRun]) -> Vec<ShapedGlyph> {
+    let mut glyphs: Vec<ShapedGlyph> = Vec::new();
+    for run in runs.iter() {
+        if run.icon.is_some() {
+            glyphs.push(icon_glyph(run, 0));
+            continue;
+        }
+        for (cluster, c) in paragraph[run.range.clone()].char_indices() {
+            if c.is_control() && c != '\t' {
+                continue;
+            }
+            let (font_index, glyph) = select_glyph(fonts, run.chain, if c == '\t' { ' ' } else { c });
+            let glyph = glyph.scaled(run.scale);
+            if let Some(last) = glyphs.last_mut() {
+                // kerning pairs only make sense within one font and size
+                if last.font == font_index && last.style == run.style && last.icon.is_none() {
+                    last.advance += fonts[font_index].font.pair_kerning(run.scale, last.id, glyph.id());
+                }
+            }
+            glyphs.push(ShapedGlyph {
+                font: font_index,
+                id: glyph.id(),
+                cluster: run.range.start + cluster,
+                advance: glyph.h_metrics().advance_width,
+                offset: (0.0, 0.0),
+                is_space: c.is_whitespace(),
+                level: 0,
+                style: run.style,
+                icon: None,
+            });
+        }
+    }
+    glyphs
+}
+
+
+/// Breaks a paragraph's glyphs into lines, wrapping at spaces when a line would exceed
+/// `max_width`. Words longer than a whole line are broken between glyphs. Lines stay in logical order.
+fn break_lines(glyphs: Vec<ShapedGlyph>, max_width: Option<f32>) -> Vec<Vec<ShapedGlyph>> {
+    let mut lines = Vec::new();
+    let mut line: Vec<ShapedGlyph> = Vec::new();
+    let mut width = 0.0;
+    // index in line just after the last run of spaces
+    let mut break_at = None;
+
+    for g in glyphs.into_iter() {
+        if let Some(max_width) = max_width {
+            // trailing spaces are allowed to overflow, they're invisible
+            if !g.is_space && width + g.advance > max_width && !line.is_empty() {
+                let rest = line.split_off(break_at.unwrap_or_else(|| line.len()));
+                lines.push(std::mem::replace(&mut line, rest));
+                width = line.iter().map(|g| g.advance).sum();
+                break_at = None;
+            }
+        }
+        width += g.advance;
+        line.push(g);
+        if g.is_space {
+            break_at = Some(line.len());
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+
+/// Reorders a line from logical to visual order by bidi level (rule L2 of UAX #9): from the
+/// highest level down to the lowest odd level, every run at that level or higher is reversed.
+fn reorder_line(glyphs: &mut [ShapedGlyph]) {
+    let max_level = glyphs.iter().map(|g| g.level).max().unwrap_or(0);
+    let min_odd_level = match glyphs.iter().map(|g| g.level).filter(|l| l % 2 == 1).min() {
+        Some(l) => l,
+        None => return
+    };
+    for level in (min_odd_level..=max_level).rev() {
+        let mut i = 0;
+        while i < glyphs.len() {
+            if glyphs[i].level >= level {
+                let start = i;
+                while i < glyphs.len() && glyphs[i].level >= level {
+                    i += 1;
+                }
+                glyphs[start..i].reverse();
+            }
+            else {
+                i += 1;
+            }
+        }
+    }
+}
+
+
+fn lay_out_paragraph(fonts: &[FontData], paragraph: &str, runs: &[StyleRun], max_width: Option<f32>,
+                     lines: &mut Vec<Line>) {
+    #[cfg(feature = "shaping")]
+    let glyphs = super::shaping::shape_paragraph(fonts, paragraph, runs);
+    #[cfg(not(feature = "shaping"))]
+    let glyphs = shape_simple(fonts, paragraph, runs);
+
+    let empty_style = runs.first().map_or(0, |r| r.style);
+    let mut paragraph_lines = break_lines(glyphs, max_width);
+    if let Some(last) = paragraph_lines.len().checked_sub(1) {
+        for (i, mut glyphs) in paragraph_lines.drain(..).enumerate() {
+            while glyphs.last().map_or(false, |g| g.is_space) {
+                glyphs.pop();
+            }
+            reorder_line(&mut glyphs);
+            let width = glyphs.iter().map(|g| g.advance).sum();
+            lines.push(Line { glyphs, width, last_in_paragraph: i == last, empty_style });
+        }
+    }
+}
+
+
+/// Offset of a line with `free` pixels left in the box, and the width added to each of its spaces.
+fn justify(align: TextAlign, free: f32, spaces: usize, last_in_paragraph: bool) -> (f32, f32) {
+    let free = free.max(0.0);
+    match align {
+        TextAlign::Left => (0.0, 0.0),
+        TextAlign::Center => (free / 2.0, 0.0),
+        TextAlign::Right => (free, 0.0),
+        TextAlign::Justify if last_in_paragraph || spaces == 0 => (0.0, 0.0),
+        TextAlign::Justify => (0.0, free / spaces as f32),
+    }
+}
+
+
+/// Lays out styled text. Each style's glyphs come from the first font in `chains[style]` that has
+/// them. Lines are as tall as the largest text on them.
+pub(super) fn layout_text(fonts: &[FontData], styled: &StyledText, chains: &[Vec<usize>], text_data: &TextData) -> Layout {
+    let scales: Vec<Scale> = styled.styles.iter().map(|s| Scale::uniform(s.size)).collect();
+    let v_metrics: Vec<VMetrics> = chains.iter().zip(scales.iter())
+                                         .map(|(chain, scale)| fonts[chain[0]].font.v_metrics(*scale))
+                                         .collect();
+    let max_width = text_data.max_width.filter(|w| *w > 0.0);
+
+    let mut lines = Vec::new();
+    let mut paragraph_start = 0;
+    for paragraph in styled.text.split('\n') {
+        let paragraph_end = paragraph_start + paragraph.len();
+        let runs: Vec<StyleRun> = styled.spans.iter().enumerate()
+            .filter(|(_, span)| span.range.start < paragraph_end && span.range.end > paragraph_start)
+            .map(|(i, span)| StyleRun {
+                range: span.range.start.max(paragraph_start) - paragraph_start
+                       .. span.range.end.min(paragraph_end) - paragraph_start,
+                chain: &chains[span.style],
+                scale: scales[span.style],
+                style: span.style,
+                icon: span.icon.as_ref().map(|_| i),
+            })
+            .collect();
+        lay_out_paragraph(fonts, paragraph, &runs, max_width, &mut lines);
+        paragraph_start = paragraph_end + 1;
+    }
+
+    let line_count = lines.len();
+    let box_width = max_width.unwrap_or_else(|| lines.iter().map(|l| l.width).fold(0.0, f32::max));
+    let mut glyphs = Vec::new();
+    let mut icons = Vec::new();
+    let (mut min_x, mut max_x) = (std::f32::INFINITY, std::f32::NEG_INFINITY);
+    let mut line_top = 0.0;
+    let mut text_bottom = 0.0;
+
+    for line in lines.into_iter() {
+        let spaces = line.glyphs.iter().filter(|g| g.is_space).count();
+        let (offset, space_extra) = justify(text_data.align, box_width - line.width, spaces, line.last_in_paragraph);
+        if !line.glyphs.is_empty() {
+            min_x = min_x.min(offset);
+            max_x = max_x.max(offset + line.width + space_extra * spaces as f32);
+        }
+
+        let metrics = if line.glyphs.is_empty() {
+            v_metrics[line.empty_style]
+        }
+        else {
+            line.glyphs.iter().map(|g| v_metrics[g.style]).fold(VMetrics { ascent: 0.0, descent: 0.0, line_gap: 0.0 },
+                |a, b| VMetrics { ascent: a.ascent.max(b.ascent), descent: a.descent.min(b.descent), line_gap: a.line_gap.max(b.line_gap) })
+        };
+        let y = line_top + metrics.ascent;
+        text_bottom = y - metrics.descent;
+        line_top += (metrics.ascent - metrics.descent + metrics.line_gap) * text_data.line_spacing;
+
+        let mut x = offset;
+        for g in line.glyphs.iter() {
+            if g.is_space {
+                x += space_extra;
+            }
+            else if let Some(span) = g.icon {
+                let bottom = y - v_metrics[g.style].descent;
+                icons.push(LayoutIcon { span, style: g.style, min: [x, bottom - g.advance], max: [x + g.advance, bottom] });
+            }
+            else {
+                let position = point(x + g.offset.0, y + g.offset.1);
+                glyphs.push(LayoutGlyph {
+                    font: g.font,
+                    glyph: fonts[g.font].font.glyph(g.id).scaled(scales[g.style]).positioned(position),
+                    style: g.style,
+                });
+            }
+            x += g.advance;
+        }
+    }
+
+    let bounds = if min_x <= max_x {
+        TextBounds { min: (min_x, 0.0), max: (max_x, text_bottom), line_count }
+    }
+    else {
+        TextBounds { min: (0.0, 0.0), max: (0.0, 0.0), line_count: 0 }
+    };
+
+    Layout { glyphs, icons, bounds }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One glyph per char of `text`, each 1 pixel wide, with the char's index as cluster.
+    fn glyphs(text: &str) -> Vec<ShapedGlyph> {
+        text.char_indices().map(|(cluster, c)| ShapedGlyph {
+            font: 0,
+            id: GlyphId(0),
+            cluster,
+            advance: 1.0,
+            offset: (0.0, 0.0),
+            is_space: c == ' ',
+            level: 0,
+            style: 0,
+            icon: None,
+        }).collect()
+    }
+
+    fn break_text(text: &str, max_width: Option<f32>) -> Vec<String> {
+        break_lines(glyphs(text), max_width).iter()
+            .map(|line| line.iter().map(|g| &text[g.cluster..g.cluster + 1]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn break_lines_without_max_width() {
+        assert_eq!(break_text("aa bb cc", None), vec!["aa bb cc"]);
+        assert_eq!(break_text("", None), vec![""]);
+        assert_eq!(break_text("", Some(5.0)), vec![""]);
+    }
+
+    #[test]
+    fn break_lines_at_spaces() {
+        assert_eq!(break_text("aa bb cc", Some(5.0)), vec!["aa bb ", "cc"]);
+        assert_eq!(break_text("aa bbbb", Some(5.0)), vec!["aa ", "bbbb"]);
+        // fits exactly
+        assert_eq!(break_text("aa bb", Some(5.0)), vec!["aa bb"]);
+    }
+
+    #[test]
+    fn trailing_spaces_overflow() {
+        assert_eq!(break_text("aaaaa   b", Some(5.0)), vec!["aaaaa   ", "b"]);
+        assert_eq!(break_text("aa   ", Some(2.0)), vec!["aa   "]);
+    }
+
+    #[test]
+    fn long_words_break_between_glyphs() {
+        assert_eq!(break_text("aaaaaaa", Some(3.0)), vec!["aaa", "aaa", "a"]);
+        assert_eq!(break_text("a bbbbbbb", Some(3.0)), vec!["a ", "bbb", "bbb", "b"]);
+    }
+
+    #[test]
+    fn reorder_mixed_levels() {
+        let mut line = glyphs("abcd");
+        line[1].level = 1;
+        line[2].level = 1;
+        reorder_line(&mut line);
+        assert_eq!(line.iter().map(|g| g.cluster).collect::<Vec<_>>(), vec![0, 2, 1, 3]);
+    }
+
+    #[test]
+    fn justify_alignments() {
+        assert_eq!(justify(TextAlign::Left, 10.0, 2, false), (0.0, 0.0));
+        assert_eq!(justify(TextAlign::Center, 10.0, 2, false), (5.0, 0.0));
+        assert_eq!(justify(TextAlign::Right, 10.0, 2, false), (10.0, 0.0));
+        assert_eq!(justify(TextAlign::Justify, 10.0, 2, false), (0.0, 5.0));
+        // overflowing lines aren't shifted or squeezed
+        assert_eq!(justify(TextAlign::Right, -3.0, 2, false), (0.0, 0.0));
+        assert_eq!(justify(TextAlign::Justify, -3.0, 2, false), (0.0, 0.0));
+    }
+
+    #[test]
+    fn justify_last_line_and_no_spaces() {
+        assert_eq!(justify(TextAlign::Justify, 10.0, 2, true), (0.0, 0.0));
+        assert_eq!(justify(TextAlign::Justify, 10.0, 0, false), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,280 @@
+//! Inline markup for styled text.
+//!
+//! Tags are written in square brackets and can be nested:
+//!
+//! * `[color=#rrggbb]` or `[color=#rrggbbaa]` ... `[/color]`
+//! * `[b]` ... `[/b]` and `[i]` ... `[/i]` switch to the family's bold / italic variants, see
+//!   [TextRenderPipeline::set_font_variants](../struct.TextRenderPipeline.html#method.set_font_variants)
+//! * `[size=24]` ... `[/size]`, in pixels
+//! * `[icon=name]` draws a texture from the registry, sized to the current text size
+//!
+//! `[[` is a literal `[`. Anything that isn't a well-formed tag is drawn as-is.
+
+
+use std::ops::Range;
+
+
+/// Replaces an icon in the laid out text.
+pub(super) const ICON_PLACEHOLDER: char = '\u{FFFC}';
+
+
+/// Style of a span of text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    pub color: [f32; 4],
+    pub size: f32,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+
+/// A span of `StyledText::text` with one style.
+pub(super) struct StyledSpan {
+    pub range: Range<usize>,
+    /// Index in `StyledText::styles`.
+    pub style: usize,
+    /// Texture name, if this span is an icon. The span covers one `ICON_PLACEHOLDER`.
+    pub icon: Option<String>,
+}
+
+
+/// Text with markup removed, split into styled spans that cover all of it.
+pub(super) struct StyledText {
+    pub text: String,
+    pub styles: Vec<TextStyle>,
+    pub spans: Vec<StyledSpan>,
+}
+
+
+impl StyledText {
+    /// Text without markup, in a single style.
+    pub fn plain(text: &str, style: TextStyle) -> StyledText {
+        StyledText {
+            text: text.to_string(),
+            styles: vec![style],
+            spans: vec![StyledSpan { range: 0..text.len(), style: 0, icon: None }],
+        }
+    }
+
+
+    /// Parses marked up text. `base` is the style outside of any tags.
+    pub fn parse(source: &str, base: TextStyle) -> StyledText {
+        let mut parser = Parser {
+            result: StyledText { text: String::with_capacity(source.len()), styles: vec![base], spans: Vec::new() },
+            stack: Vec::new(),
+            span_start: 0,
+        };
+
+        let mut rest = source;
+        while let Some(open) = rest.find('[') {
+            parser.result.text.push_str(&rest[..open]);
+            rest = &rest[open..];
+            if rest.starts_with("[[") {
+                parser.result.text.push('[');
+                rest = &rest[2..];
+                continue;
+            }
+            let handled = match rest.find(']') {
+                Some(close) if parser.tag(&rest[1..close]) => {
+                    rest = &rest[close + 1..];
+                    true
+                },
+                _ => false
+            };
+            if !handled {
+                parser.result.text.push('[');
+                rest = &rest[1..];
+            }
+        }
+        parser.result.text.push_str(rest);
+        parser.finish_span();
+        parser.result
+    }
+}
+
+
+struct Parser {
+    result: StyledText,
+    /// Open tags and the style index each one set.
+    stack: Vec<(&'static str, usize)>,
+    span_start: usize,
+}
+
+
+impl Parser {
+    fn current_style(&self) -> usize {
+        self.stack.last().map_or(0, |(_, style)| *style)
+    }
+
+
+    /// Ends the span of text since the last style change.
+    fn finish_span(&mut self) {
+        let end = self.result.text.len();
+        if end > self.span_start {
+            let style = self.current_style();
+            self.result.spans.push(StyledSpan { range: self.span_start..end, style, icon: None });
+        }
+        self.span_start = end;
+    }
+
+
+    fn push_style(&mut self, tag: &'static str, style: TextStyle) {
+        self.finish_span();
+        self.result.styles.push(style);
+        self.stack.push((tag, self.result.styles.len() - 1));
+    }
+
+
+    /// Applies a tag (without brackets). Returns false if it isn't a valid tag.
+    fn tag(&mut self, tag: &str) -> bool {
+        let mut style = self.result.styles[self.current_style()].clone();
+
+        if tag.starts_with('/') {
+            let name = &tag[1..];
+            return match self.stack.iter().rposition(|(open, _)| *open == name) {
+                Some(index) => {
+                    self.finish_span();
+                    // closing an outer tag also closes anything still open inside it
+                    self.stack.truncate(index);
+                    true
+                },
+                None => false
+            };
+        }
+
+        let (name, value) = match tag.find('=') {
+            Some(eq) => (&tag[..eq], Some(&tag[eq + 1..])),
+            None => (tag, None)
+        };
+        match (name, value) {
+            ("b", None) => {
+                style.bold = true;
+                self.push_style("b", style);
+            },
+            ("i", None) => {
+                style.italic = true;
+                self.push_style("i", style);
+            },
+            ("color", Some(value)) => match parse_color(value) {
+                Some(color) => {
+                    style.color = color;
+                    self.push_style("color", style);
+                },
+                None => return false
+            },
+            ("size", Some(value)) => match value.parse::<f32>() {
+                Ok(size) if size > 0.0 => {
+                    style.size = size;
+                    self.push_style("size", style);
+                },
+                _ => return false
+            },
+            ("icon", Some(name)) if !name.is_empty() => {
+                self.finish_span();
+                let style = self.current_style();
+                self.result.text.push(ICON_PLACEHOLDER);
+                let end = self.result.text.len();
+                self.result.spans.push(StyledSpan { range: self.span_start..end, style, icon: Some(name.to_string()) });
+                self.span_start = end;
+            },
+            _ => return false
+        }
+        true
+    }
+}
+
+
+/// Parses `#rrggbb` or `#rrggbbaa`.
+fn parse_color(value: &str) -> Option<[f32; 4]> {
+    let hex = value.trim_start_matches('#');
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i*2..i*2 + 2], 16).ok().map(|c| c as f32 / 255.0);
+    Some([channel(0)?, channel(1)?, channel(2)?, if hex.len() == 8 { channel(3)? } else { 1.0 }])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> TextStyle {
+        TextStyle { color: [1.0, 1.0, 1.0, 1.0], size: 16.0, bold: false, italic: false }
+    }
+
+    /// Text of each span, with its style.
+    fn spans(styled: &StyledText) -> Vec<(&str, &TextStyle)> {
+        styled.spans.iter().map(|s| (&styled.text[s.range.clone()], &styled.styles[s.style])).collect()
+    }
+
+    #[test]
+    fn plain_text() {
+        let styled = StyledText::parse("hello", base());
+        assert_eq!(styled.text, "hello");
+        assert_eq!(spans(&styled), vec![("hello", &base())]);
+        assert!(StyledText::parse("", base()).spans.is_empty());
+    }
+
+    #[test]
+    fn nested_tags() {
+        let styled = StyledText::parse("a[b]b[i]c[/i]d[/b]e", base());
+        assert_eq!(styled.text, "abcde");
+        let bold = TextStyle { bold: true, ..base() };
+        let bold_italic = TextStyle { bold: true, italic: true, ..base() };
+        assert_eq!(spans(&styled), vec![("a", &base()), ("b", &bold), ("c", &bold_italic), ("d", &bold), ("e", &base())]);
+    }
+
+    #[test]
+    fn color_and_size() {
+        let styled = StyledText::parse("[color=#ff000080]red[/color][size=24]big[/size]", base());
+        assert_eq!(spans(&styled), vec![
+            ("red", &TextStyle { color: [1.0, 0.0, 0.0, 128.0 / 255.0], ..base() }),
+            ("big", &TextStyle { size: 24.0, ..base() }),
+        ]);
+        let styled = StyledText::parse("[color=#00ff00]x", base());
+        assert_eq!(spans(&styled), vec![("x", &TextStyle { color: [0.0, 1.0, 0.0, 1.0], ..base() })]);
+    }
+
+    #[test]
+    fn escaped_bracket() {
+        assert_eq!(StyledText::parse("[[b]x", base()).text, "[b]x");
+        assert_eq!(StyledText::parse("a[[[b]x[/b]", base()).text, "a[x");
+    }
+
+    #[test]
+    fn malformed_tags_are_drawn() {
+        assert_eq!(StyledText::parse("[b", base()).text, "[b");
+        assert_eq!(StyledText::parse("a]b", base()).text, "a]b");
+        assert_eq!(StyledText::parse("[foo]x", base()).text, "[foo]x");
+        assert_eq!(StyledText::parse("[size=-1]x", base()).text, "[size=-1]x");
+        assert_eq!(StyledText::parse("[size=big]x", base()).text, "[size=big]x");
+        assert_eq!(StyledText::parse("[color=#ff00]x", base()).text, "[color=#ff00]x");
+        assert_eq!(StyledText::parse("[icon=]x", base()).text, "[icon=]x");
+        assert_eq!(StyledText::parse("[b=1]x", base()).text, "[b=1]x");
+    }
+
+    #[test]
+    fn unbalanced_closing_tags() {
+        // closing a tag that isn't open is drawn as-is
+        let styled = StyledText::parse("[/b]x", base());
+        assert_eq!(spans(&styled), vec![("[/b]x", &base())]);
+        // closing an outer tag closes the tags inside it
+        let styled = StyledText::parse("[i][b]x[/i]y[/b]", base());
+        assert_eq!(styled.text, "xy[/b]");
+        assert_eq!(spans(&styled), vec![("x", &TextStyle { bold: true, italic: true, ..base() }), ("y[/b]", &base())]);
+        // unclosed tags last until the end
+        let styled = StyledText::parse("a[b]x", base());
+        assert_eq!(spans(&styled), vec![("a", &base()), ("x", &TextStyle { bold: true, ..base() })]);
+    }
+
+    #[test]
+    fn icons() {
+        let styled = StyledText::parse("a[b][icon=coin][/b]b", base());
+        assert_eq!(styled.text, format!("a{}b", ICON_PLACEHOLDER));
+        let icons: Vec<Option<&str>> = styled.spans.iter().map(|s| s.icon.as_ref().map(|i| i.as_str())).collect();
+        assert_eq!(icons, vec![None, Some("coin"), None]);
+        assert_eq!(styled.styles[styled.spans[1].style], TextStyle { bold: true, ..base() });
+        assert_eq!(&styled.text[styled.spans[1].range.clone()], ICON_PLACEHOLDER.to_string());
+    }
+}
@@ -0,0 +1,753 @@
+//! Text rendering, either from a per-size bitmap glyph cache or a signed distance field atlas.
+
+pub mod layout;
+pub mod markup;
+pub mod sdf;
+#[cfg(feature = "shaping")]
+mod shaping;
+
+use std::sync::Arc;
+
+use vulkano::buffer::BufferUsage;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, AutoCommandBuffer, DynamicState};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::{Device, Queue};
+use vulkano::format::{R8Unorm, R8G8B8A8Srgb};
+use vulkano::framebuffer::{FramebufferAbstract, RenderPass, RenderPassDesc, Subpass, RenderPassAbstract};
+use vulkano::image::{AttachmentImage, ImageUsage};
+use vulkano::image::immutable::ImmutableImage;
+use vulkano::memory::pool::{PotentialDedicatedAllocation, StdMemoryPoolAlloc};
+use vulkano::pipeline::viewport::{Scissor, Viewport};
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::pipeline::depth_stencil::{DepthStencil, Compare};
+use vulkano::sampler::{Sampler, Filter, SamplerAddressMode, MipmapMode};
+use rusttype::Font;
+use hashbrown::{HashMap, HashSet};
+use rusttype::gpu_cache::Cache;
+use cgmath::{EuclideanSpace, Matrix, Matrix3, Matrix4, Point3, Vector3, Vector4};
+
+use crate::renderpass::LinesRenderPass;
+use crate::geometry::vertex::VertexPositionUVColor;
+use crate::renderer::RenderInfo;
+use crate::shader::text as TextShaders;
+use crate::pipeline::RenderPipelineAbstract;
+use crate::buffer::CpuAccessibleBufferXalloc;
+use crate::cpu_pool::XallocCpuBufferPool;
+use self::layout::{Layout, LayoutGlyph, layout_text};
+pub use self::layout::{TextAlign, TextBounds};
+use self::markup::StyledText;
+pub use self::markup::TextStyle;
+use self::sdf::{SdfAtlas, SDF_BASE_SIZE, SDF_SPREAD};
+
+
+/// The size of each font's cache texture, in pixels (i.e. 512 x 512)
+pub const CACHE_SIZE: usize = 512;
+
+
+/// Family used when a `TextData` asks for a family that isn't registered. No fonts ship with the crate,
+/// so a font has to be registered under this name for text to be drawn.
+pub const DEFAULT_FONT_FAMILY: &str = "Roboto Regular";
+
+
+/// How glyphs are rasterized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextRenderMode {
+    /// Glyphs are rasterized at the exact size into a per-font cache. Sharpest at small sizes,
+    /// but every size takes up separate cache space.
+    Bitmap,
+    /// Glyphs are drawn from a signed distance field atlas. Scales smoothly and supports `TextEffects`.
+    Sdf,
+}
+
+
+/// Outline, drop shadow and glow. Only used in `TextRenderMode::Sdf`. Widths and offsets are in screen
+/// pixels, also for world-space text and spans resized with markup, and are limited by the atlas spread
+/// (8px for glyphs drawn 48px tall, proportionally more for larger glyphs).
+#[derive(Clone)]
+pub struct TextEffects {
+    pub outline_color: [f32; 4],
+    /// 0.0 = no outline.
+    pub outline_width: f32,
+    /// Alpha 0.0 = no shadow.
+    pub shadow_color: [f32; 4],
+    pub shadow_offset: (f32, f32),
+    pub shadow_softness: f32,
+    pub glow_color: [f32; 4],
+    /// 0.0 = no glow.
+    pub glow_width: f32,
+}
+impl Default for TextEffects {
+    fn default() -> Self {
+        Self {
+            outline_color: [0.0, 0.0, 0.0, 1.0],
+            outline_width: 0.0,
+            shadow_color: [0.0, 0.0, 0.0, 0.0],
+            shadow_offset: (2.0, 2.0),
+            shadow_softness: 1.0,
+            glow_color: [1.0, 1.0, 1.0, 0.5],
+            glow_width: 0.0,
+        }
+    }
+}
+
+
+/// Where text is drawn. Text is laid out with its origin at the top left of the first line.
+///
+/// In the world-space placements, `scale` is world units per pixel of `TextData::size`, and text
+/// is depth-tested against the scene if `TextData::depth_test` is set.
+#[derive(Clone, Copy, Debug)]
+pub enum TextPlacement {
+    /// At `TextData::position`, in pixels from the top left of the screen.
+    Screen,
+    /// At a point in world space, always facing the camera.
+    Billboard { position: Point3<f32>, scale: f32 },
+    /// At a point in world space, facing the camera with a constant on-screen size. Useful for name tags.
+    ScreenSizeBillboard { position: Point3<f32> },
+    /// On the XY plane of the given transform, with +Y up.
+    Transform { transform: Matrix4<f32>, scale: f32 },
+}
+
+
+#[derive(Clone)]
+pub struct TextData {
+    pub text: String,
+    /// Screen position in pixels, for `TextPlacement::Screen`.
+    pub position: (i32, i32),
+    pub size: f32,
+    pub color: [f32; 4],
+    pub family: String,
+    pub render_mode: TextRenderMode,
+    pub effects: TextEffects,
+    pub placement: TextPlacement,
+    /// Whether world-space text is hidden behind scene geometry. Screen text is always drawn on top.
+    pub depth_test: bool,
+    /// Width in pixels to wrap lines at. `None` only breaks lines at newlines.
+    pub max_width: Option<f32>,
+    pub align: TextAlign,
+    /// Multiplier for the font's line height.
+    pub line_spacing: f32,
+    /// Rectangle to clip the text to, as (x, y, width, height) in pixels from the top left of the screen.
+    pub clip_rect: Option<(i32, i32, u32, u32)>,
+    /// Whether `text` contains markup to parse. See the [markup](markup/index.html) module.
+    pub markup: bool,
+}
+impl Default for TextData {
+    fn default() -> Self {
+        Self {
+            text: "".to_string(),
+            position: (0, 0),
+            size: 18.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            family: DEFAULT_FONT_FAMILY.to_string(),
+            render_mode: TextRenderMode::Bitmap,
+            effects: TextEffects::default(),
+            placement: TextPlacement::Screen,
+            depth_test: true,
+            max_width: None,
+            align: TextAlign::Left,
+            line_spacing: 1.0,
+            clip_rect: None,
+            markup: false,
+        }
+    }
+}
+
+
+pub struct FontData {
+    pub font: Box<Font<'static>>,
+    /// The font file, shared with `font`. Used by the shaper.
+    pub bytes: Arc<[u8]>,
+    pub cache: Box<Cache<'static>>,
+    pub cache_buffer: Arc<CpuAccessibleBufferXalloc<[u8]>>,
+    pub cache_texture: Arc<AttachmentImage<R8Unorm, PotentialDedicatedAllocation<StdMemoryPoolAlloc>>>,
+    /// True if `cache_buffer` has changed since it was last copied to `cache_texture`.
+    pub cache_dirty: bool,
+    pub sdf_atlas: SdfAtlas,
+}
+
+
+impl FontData {
+    fn new(device: Arc<Device>, bytes: Arc<[u8]>, font: Font<'static>) -> Self {
+        FontData {
+            font: Box::new(font),
+            bytes,
+            cache: Box::new(Cache::builder().dimensions(CACHE_SIZE as u32, CACHE_SIZE as u32).build()),
+            cache_buffer: CpuAccessibleBufferXalloc::from_iter(device.clone(),
+                                                               BufferUsage::all(),
+                                                               (0 .. CACHE_SIZE*CACHE_SIZE).map(|_| 0u8)
+            ).expect("failed to create buffer"),
+            cache_texture: AttachmentImage::with_usage(
+                device.clone(),
+                [CACHE_SIZE as u32, CACHE_SIZE as u32],
+                R8Unorm,
+                *CACHE_TEXTURE_USAGE).unwrap(),
+            cache_dirty: false,
+            sdf_atlas: SdfAtlas::new(device),
+        }
+    }
+}
+
+
+/// Families used for bold and italic text in markup. Missing variants use the regular family.
+#[derive(Clone, Debug, Default)]
+pub struct FontVariants {
+    pub bold: Option<String>,
+    pub italic: Option<String>,
+    pub bold_italic: Option<String>,
+}
+
+
+lazy_static! {
+    static ref CACHE_TEXTURE_USAGE: ImageUsage = ImageUsage {
+        transfer_destination: true,
+        sampled: true,
+        .. ImageUsage::none()
+    };
+}
+
+
+/// Appends a quad as two triangles. Positions are in pixels relative to the text origin, and are
+/// moved into place by the transform from `text_transform`.
+fn push_quad(vertices: &mut Vec<VertexPositionUVColor>, min: [f32; 2], max: [f32; 2], uv_min: [f32; 2], uv_max: [f32; 2],
+             color: [f32; 4]) {
+    let (x_min, y_min, x_max, y_max) = (min[0], min[1], max[0], max[1]);
+    let corners = [
+        ([x_min, y_max], [uv_min[0], uv_max[1]]),
+        ([x_min, y_min], [uv_min[0], uv_min[1]]),
+        ([x_max, y_min], [uv_max[0], uv_min[1]]),
+        ([x_max, y_min], [uv_max[0], uv_min[1]]),
+        ([x_max, y_max], [uv_max[0], uv_max[1]]),
+        ([x_min, y_max], [uv_min[0], uv_max[1]]),
+    ];
+    for (position, uv) in corners.iter() {
+        vertices.push(VertexPositionUVColor {
+            position: [position[0], position[1], 0.0],
+            uv: *uv,
+            color,
+        });
+    }
+}
+
+
+/// Gets the transform from text-local pixels to clip space, or None if the text is behind the camera.
+fn text_transform(text_data: &TextData, info: &RenderInfo) -> Option<Matrix4<f32>> {
+    let (width, height) = (info.dimensions[0] as f32, info.dimensions[1] as f32);
+    let pixels_to_ndc = Matrix4::from_nonuniform_scale(2.0 / width, 2.0 / height, 1.0);
+    // text is drawn after TAA, so it shouldn't be jittered
+    let view_proj = info.unjittered_proj_mat * info.view_mat;
+    // layout is y-down, world space is y-up
+    let flip = |scale: f32| Matrix4::from_nonuniform_scale(scale, -scale, scale);
+
+    match text_data.placement {
+        TextPlacement::Screen => {
+            let (x, y) = text_data.position;
+            Some(Matrix4::from_translation(Vector3::new(-1.0, -1.0, 0.0)) * pixels_to_ndc
+                 * Matrix4::from_translation(Vector3::new(x as f32, y as f32, 0.0)))
+        },
+        TextPlacement::Billboard { position, scale } => {
+            // inverse of the view rotation, so the text plane faces the camera
+            let v = info.view_mat;
+            let camera_rotation = Matrix4::from(Matrix3::from_cols(v.x.truncate(), v.y.truncate(), v.z.truncate()).transpose());
+            Some(view_proj * Matrix4::from_translation(position.to_vec()) * camera_rotation * flip(scale))
+        },
+        TextPlacement::ScreenSizeBillboard { position } => {
+            let anchor = view_proj * position.to_homogeneous();
+            if anchor.w <= 0.0 {
+                return None;
+            }
+            // offset from the projected anchor by the pixel position, scaled by w so the size is constant
+            let offset = Matrix4::from_cols(
+                Vector4::new(anchor.w, 0.0, 0.0, 0.0),
+                Vector4::new(0.0, anchor.w, 0.0, 0.0),
+                Vector4::new(0.0, 0.0, 0.0, 0.0),
+                anchor);
+            Some(offset * pixels_to_ndc)
+        },
+        TextPlacement::Transform { transform, scale } => {
+            Some(view_proj * transform * flip(scale))
+        },
+    }
+}
+
+
+/// Gets the dynamic state for drawing `text_data`, with a scissor for its clip rect.
+fn text_dynamic_state(text_data: &TextData, info: &RenderInfo) -> DynamicState {
+    let (width, height) = (info.dimensions[0] as i32, info.dimensions[1] as i32);
+    let (x0, y0, x1, y1) = match text_data.clip_rect {
+        Some((x, y, w, h)) => (x.max(0).min(width), y.max(0).min(height),
+                               (x + w as i32).max(0).min(width), (y + h as i32).max(0).min(height)),
+        None => (0, 0, width, height)
+    };
+    DynamicState {
+        line_width: None,
+        viewports: Some(vec![Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [info.dimensions[0] as f32, info.dimensions[1] as f32],
+            depth_range: 0.0..1.0,
+        }]),
+        scissors: Some(vec![Scissor {
+            origin: [x0, y0],
+            dimensions: [(x1 - x0).max(0) as u32, (y1 - y0).max(0) as u32],
+        }]),
+        compare_mask: None,
+        write_mask: None,
+        reference: None
+    }
+}
+
+
+fn is_depth_tested(text_data: &TextData) -> bool {
+    match text_data.placement {
+        TextPlacement::Screen => false,
+        _ => text_data.depth_test
+    }
+}
+
+
+/// Writes glyphs queued in the font's cache into its cache buffer.
+fn cache_queued_glyphs(font: &mut FontData) {
+    let FontData { cache, cache_buffer, cache_dirty, .. } = font;
+    let result = cache.cache_queued(|rect, data| {
+        let x1 = rect.min.x as usize;
+        let x2 = x1 + rect.width() as usize;
+        let y1 = rect.min.y as usize;
+        let y2 = y1 + rect.height() as usize;
+
+        let mut lock = cache_buffer.write().unwrap();
+        let mut i = 0;
+        for y in y1..y2 {
+            for x in x1..x2 {
+                lock[y*(CACHE_SIZE as usize)+x] = data[i];
+                i += 1;
+            }
+        }
+        *cache_dirty = true;
+    });
+    if let Err(e) = result {
+        warn!(Renderer, "Failed to cache glyphs, some text won't be drawn: {:?}", e);
+    }
+}
+
+
+/// Builds quads for the glyphs from `font_index` using its bitmap cache. Glyphs must already be cached.
+fn build_bitmap_vertices(font: &FontData, font_index: usize, glyphs: &[LayoutGlyph], styles: &[TextStyle])
+                         -> Vec<VertexPositionUVColor> {
+    let mut vertices = Vec::new();
+    for g in glyphs.iter().filter(|g| g.font == font_index) {
+        if let Ok(Some((uv_rect, screen_rect))) = font.cache.rect_for(0, &g.glyph) {
+            push_quad(&mut vertices,
+                      [screen_rect.min.x as f32, screen_rect.min.y as f32],
+                      [screen_rect.max.x as f32, screen_rect.max.y as f32],
+                      [uv_rect.min.x, uv_rect.min.y], [uv_rect.max.x, uv_rect.max.y],
+                      styles[g.style].color);
+        }
+    }
+    vertices
+}
+
+
+/// Builds quads for the glyphs from `font_index` using its SDF atlas, generating any that are missing.
+fn build_sdf_vertices(font: &mut FontData, font_index: usize, glyphs: &[LayoutGlyph], styles: &[TextStyle])
+                      -> Vec<VertexPositionUVColor> {
+    let mut vertices = Vec::new();
+    for g in glyphs.iter().filter(|g| g.font == font_index) {
+        if let Some(entry) = font.sdf_atlas.glyph(&font.font, g.glyph.id()) {
+            let scale = g.glyph.scale().y / SDF_BASE_SIZE;
+            let origin = g.glyph.position();
+            push_quad(&mut vertices,
+                      [origin.x + entry.offset_min[0] * scale, origin.y + entry.offset_min[1] * scale],
+                      [origin.x + entry.offset_max[0] * scale, origin.y + entry.offset_max[1] * scale],
+                      entry.uv_min, entry.uv_max, styles[g.style].color);
+        }
+    }
+    vertices
+}
+
+
+/// What a `TextDraw` samples from.
+enum TextDrawSource {
+    /// Index in `TextRenderPipeline::fonts`.
+    Font(usize),
+    Icon(Arc<ImmutableImage<R8G8B8A8Srgb>>),
+}
+
+
+/// Vertices for the glyphs of one `TextData` that come from the same font, or for one of its icons.
+struct TextDraw<'a> {
+    text_data: &'a TextData,
+    source: TextDrawSource,
+    vertices: Vec<VertexPositionUVColor>,
+    transform: Matrix4<f32>,
+}
+
+
+pub struct TextRenderPipeline {
+    /// Bitmap text pipelines, indexed by whether they're depth tested.
+    vulkan_pipelines: [Arc<dyn GraphicsPipelineAbstract + Send + Sync>; 2],
+    /// SDF text pipelines, indexed by whether they're depth tested.
+    sdf_pipelines: [Arc<dyn GraphicsPipelineAbstract + Send + Sync>; 2],
+    /// Inline icon pipelines, indexed by whether they're depth tested.
+    icon_pipelines: [Arc<dyn GraphicsPipelineAbstract + Send + Sync>; 2],
+    pub framebuffers: Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>,
+    renderpass: Arc<RenderPass<LinesRenderPass>>,
+    device: Arc<Device>,
+    fonts: Vec<FontData>,
+    /// Family name to index in `fonts`.
+    families: HashMap<String, usize>,
+    /// Per-family fallback chains, tried in order when a font is missing a glyph.
+    fallbacks: HashMap<String, Vec<String>>,
+    /// Fallbacks tried after the per-family chain, e.g. for CJK or emoji fonts.
+    default_fallbacks: Vec<String>,
+    /// Families that weren't registered when requested, so the warning is only logged once.
+    warned_families: HashSet<String>,
+    /// Bold / italic variants of each family.
+    variants: HashMap<String, FontVariants>,
+    /// Icons that weren't in the texture registry, so the warning is only logged once.
+    warned_icons: HashSet<String>,
+    sampler: Arc<Sampler>,
+    sdf_sampler: Arc<Sampler>,
+    effects_buffer_pool: XallocCpuBufferPool<TextShaders::sdf_fragment::ty::Effects>,
+    /// Only draws `TextPlacement::Screen` text. Set by the renderer in split-screen, where there's no main
+    /// view depth to test world-space text against.
+    pub screen_only: bool,
+}
+
+
+impl TextRenderPipeline {
+    pub fn new(info: &RenderInfo) -> Self {
+        let vs = TextShaders::vertex::Shader::load(info.device.clone()).expect("failed to create shader module");
+        let fs = TextShaders::fragment::Shader::load(info.device.clone()).expect("failed to create shader module");
+        let sdf_fs = TextShaders::sdf_fragment::Shader::load(info.device.clone()).expect("failed to create shader module");
+        let icon_fs = TextShaders::icon_fragment::Shader::load(info.device.clone()).expect("failed to create shader module");
+
+        let renderpass = Arc::new(
+            LinesRenderPass {}
+                .build_render_pass(info.device.clone())
+                .unwrap()
+        );
+
+        // world-space text is tested against the scene but doesn't write depth, so overlapping glyph quads don't clip each other
+        let depth_tested = DepthStencil {
+            depth_write: false,
+            depth_compare: info.depth_mode.compare(Compare::Less),
+            ..DepthStencil::simple_depth_test()
+        };
+        macro_rules! text_pipeline {
+            ($fs:expr, $depth_stencil:expr) => {
+                Arc::new(GraphicsPipeline::start()
+                    .vertex_input_single_buffer::<VertexPositionUVColor>()
+                    .vertex_shader(vs.main_entry_point(), ())
+                    .triangle_list()
+                    .viewports_dynamic_scissors_dynamic(1)
+                    .fragment_shader($fs.main_entry_point(), ())
+                    .depth_stencil($depth_stencil)
+                    .blend_alpha_blending()
+                    .render_pass(Subpass::from(renderpass.clone(), 0).unwrap())
+                    .build(info.device.clone())
+                    .unwrap()) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>
+            }
+        }
+        let vulkan_pipelines = [text_pipeline!(fs, DepthStencil::disabled()), text_pipeline!(fs, depth_tested.clone())];
+        let sdf_pipelines = [text_pipeline!(sdf_fs, DepthStencil::disabled()), text_pipeline!(sdf_fs, depth_tested.clone())];
+        let icon_pipelines = [text_pipeline!(icon_fs, DepthStencil::disabled()), text_pipeline!(icon_fs, depth_tested)];
+
+        TextRenderPipeline {
+            vulkan_pipelines,
+            sdf_pipelines,
+            icon_pipelines,
+            framebuffers: None,
+            renderpass,
+            device: info.device.clone(),
+            fonts: Vec::new(),
+            families: HashMap::new(),
+            fallbacks: HashMap::new(),
+            default_fallbacks: Vec::new(),
+            warned_families: HashSet::new(),
+            variants: HashMap::new(),
+            warned_icons: HashSet::new(),
+            sampler: Sampler::new(info.device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Nearest,
+                                  SamplerAddressMode::Repeat, SamplerAddressMode::Repeat, SamplerAddressMode::Repeat,
+                                  0.0, 4.0, 0.0, 0.0).unwrap(),
+            sdf_sampler: Sampler::new(info.device.clone(), Filter::Linear, Filter::Linear, MipmapMode::Nearest,
+                                      SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                      0.0, 1.0, 0.0, 0.0).unwrap(),
+            effects_buffer_pool: XallocCpuBufferPool::<TextShaders::sdf_fragment::ty::Effects>::new(info.device.clone(), BufferUsage::all()),
+            screen_only: false,
+        }
+    }
+
+
+    /// Registers a font under the given family name, replacing any font already registered with that name.
+    pub fn register_font(&mut self, name: &str, bytes: Vec<u8>) -> Result<(), rusttype::Error> {
+        let bytes: Arc<[u8]> = bytes.into();
+        let font = Font::from_bytes(bytes.clone())?;
+        let data = FontData::new(self.device.clone(), bytes, font);
+        match self.families.get(name) {
+            Some(&index) => self.fonts[index] = data,
+            None => {
+                self.families.insert(name.to_string(), self.fonts.len());
+                self.fonts.push(data);
+            }
+        }
+        self.warned_families.remove(name);
+        Ok(())
+    }
+
+
+    /// Sets the families to try, in order, when `family` is missing a glyph.
+    pub fn set_fallbacks(&mut self, family: &str, fallbacks: Vec<String>) {
+        self.fallbacks.insert(family.to_string(), fallbacks);
+    }
+
+
+    /// Sets the families to try for every font after its own fallbacks, e.g. CJK and emoji fonts.
+    pub fn set_default_fallbacks(&mut self, fallbacks: Vec<String>) {
+        self.default_fallbacks = fallbacks;
+    }
+
+
+    /// Sets the families used for `[b]` and `[i]` markup in text using `family`.
+    pub fn set_font_variants(&mut self, family: &str, variants: FontVariants) {
+        self.variants.insert(family.to_string(), variants);
+    }
+
+
+    /// Returns true if a font is registered with the given family name.
+    pub fn has_font(&self, name: &str) -> bool {
+        self.families.contains_key(name)
+    }
+
+
+    /// Measures `text_data` as it would be laid out when drawn, without drawing it.
+    pub fn measure_text(&mut self, text_data: &TextData) -> TextBounds {
+        match self.layout(text_data) {
+            Some((_, layout)) => layout.bounds,
+            None => TextBounds { min: (0.0, 0.0), max: (0.0, 0.0), line_count: 0 }
+        }
+    }
+
+
+    /// Parses and lays out `text_data`. None if no fonts are registered.
+    fn layout(&mut self, text_data: &TextData) -> Option<(StyledText, Layout)> {
+        if self.fonts.is_empty() {
+            return None;
+        }
+        let text = text_data.text.replace("\r\n", "\n").replace('\r', "\n");
+        let base = TextStyle { color: text_data.color, size: text_data.size, bold: false, italic: false };
+        let styled = if text_data.markup { StyledText::parse(&text, base) } else { StyledText::plain(&text, base) };
+
+        let chains: Vec<Vec<usize>> = styled.styles.iter().map(|style| {
+            let family = self.style_family(&text_data.family, style);
+            self.font_chain(&family)
+        }).collect();
+        let layout = layout_text(&self.fonts, &styled, &chains, text_data);
+        Some((styled, layout))
+    }
+
+
+    /// Gets the family to use for a style, i.e. a bold or italic variant if one is registered.
+    fn style_family(&self, family: &str, style: &TextStyle) -> String {
+        let variant = self.variants.get(family).and_then(|v| match (style.bold, style.italic) {
+            (true, true) => v.bold_italic.as_ref().or(v.bold.as_ref()),
+            (true, false) => v.bold.as_ref(),
+            (false, true) => v.italic.as_ref(),
+            (false, false) => None,
+        });
+        match variant {
+            Some(name) if self.families.contains_key(name) => name.clone(),
+            _ => family.to_string()
+        }
+    }
+
+
+    /// Resolves a family to the indices of the fonts to search for glyphs, primary font first.
+    /// Unregistered families fall back to `DEFAULT_FONT_FAMILY`, or the first registered font.
+    /// Empty if no fonts are registered.
+    fn font_chain(&mut self, family: &str) -> Vec<usize> {
+        let primary = match self.families.get(family) {
+            Some(&index) => index,
+            None => {
+                if self.fonts.is_empty() {
+                    return Vec::new();
+                }
+                if self.warned_families.insert(family.to_string()) {
+                    warn!(Renderer, "Font family \"{}\" isn't registered, falling back to the default font", family);
+                }
+                self.families.get(DEFAULT_FONT_FAMILY).cloned().unwrap_or(0)
+            }
+        };
+
+        let mut chain = vec![primary];
+        let family_fallbacks = self.fallbacks.get(family).into_iter().flatten();
+        for name in family_fallbacks.chain(self.default_fallbacks.iter()) {
+            if let Some(&index) = self.families.get(name) {
+                if !chain.contains(&index) {
+                    chain.push(index);
+                }
+            }
+        }
+        chain
+    }
+}
+
+
+impl RenderPipelineAbstract for TextRenderPipeline {
+    fn get_framebuffers_mut(&mut self) -> &mut Option<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>> {
+        &mut self.framebuffers
+    }
+
+
+    fn get_renderpass(&self) -> Arc<dyn RenderPassAbstract + Send + Sync> {
+        self.renderpass.clone() as Arc<dyn RenderPassAbstract + Send + Sync>
+    }
+
+    fn build_command_buffer(&mut self, info: &RenderInfo) -> (AutoCommandBuffer, Arc<Queue>) {
+        let lock = info.render_queues.read().unwrap();
+
+        // lay out everything first, so glyph caches and atlases can be uploaded before the render pass
+        let mut layouts = Vec::new();
+        for text_data in (*lock).text.iter().chain((*lock).debug.text()) {
+            match text_data.placement {
+                TextPlacement::Screen => {},
+                _ if self.screen_only => continue,
+                _ => {}
+            }
+            let transform = match text_transform(text_data, info) {
+                Some(t) => t,
+                None => continue
+            };
+            let (styled, layout) = match self.layout(text_data) {
+                Some(l) => l,
+                None => continue
+            };
+            if text_data.render_mode == TextRenderMode::Bitmap {
+                for g in layout.glyphs.iter() {
+                    self.fonts[g.font].cache.queue_glyph(0, g.glyph.clone());
+                }
+            }
+            layouts.push((text_data, transform, styled, layout));
+        }
+        for font in self.fonts.iter_mut() {
+            cache_queued_glyphs(font);
+        }
+
+        let mut draws = Vec::new();
+        for (text_data, transform, styled, layout) in layouts.iter() {
+            let mut used_fonts: Vec<usize> = layout.glyphs.iter().map(|g| g.font).collect();
+            used_fonts.sort();
+            used_fonts.dedup();
+            for font_index in used_fonts.into_iter() {
+                let font = &mut self.fonts[font_index];
+                let vertices = match text_data.render_mode {
+                    TextRenderMode::Bitmap => build_bitmap_vertices(font, font_index, &layout.glyphs, &styled.styles),
+                    TextRenderMode::Sdf => build_sdf_vertices(font, font_index, &layout.glyphs, &styled.styles),
+                };
+                if !vertices.is_empty() {
+                    draws.push(TextDraw { text_data: *text_data, source: TextDrawSource::Font(font_index), vertices, transform: *transform });
+                }
+            }
+
+            for icon in layout.icons.iter() {
+                let name = styled.spans[icon.span].icon.as_ref().unwrap();
+                let texture = match info.tex_registry.get(name) {
+                    Some(t) => t,
+                    None => {
+                        if self.warned_icons.insert(name.clone()) {
+                            warn!(Renderer, "Text icon \"{}\" isn't in the texture registry", name);
+                        }
+                        continue;
+                    }
+                };
+                let mut vertices = Vec::new();
+                push_quad(&mut vertices, icon.min, icon.max, [0.0, 0.0], [1.0, 1.0],
+                          [1.0, 1.0, 1.0, styled.styles[icon.style].color[3]]);
+                draws.push(TextDraw { text_data: *text_data, source: TextDrawSource::Icon(texture), vertices, transform: *transform });
+            }
+        }
+
+        let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(info.device.clone(), info.queue_main.family())
+            .unwrap();
+        for font in self.fonts.iter_mut() {
+            if font.cache_dirty {
+                cb = cb.copy_buffer_to_image(font.cache_buffer.clone(), font.cache_texture.clone()).unwrap();
+                font.cache_dirty = false;
+            }
+            if font.sdf_atlas.dirty {
+                cb = cb.copy_buffer_to_image(font.sdf_atlas.buffer.clone(), font.sdf_atlas.texture.clone()).unwrap();
+                font.sdf_atlas.dirty = false;
+            }
+        }
+        cb = cb.begin_render_pass(
+                self.framebuffers.as_ref().unwrap()[info.image_num].clone(), false,
+                vec![::vulkano::format::ClearValue::None, ::vulkano::format::ClearValue::None]).unwrap();
+
+        for draw in draws.into_iter() {
+            let text_data = draw.text_data;
+            let depth_tested = is_depth_tested(text_data) as usize;
+            let dynamic_state = text_dynamic_state(text_data, info);
+
+            let vertex_buffer = CpuAccessibleBufferXalloc::<[VertexPositionUVColor]>::from_iter(
+                info.device.clone(),
+                BufferUsage::all(),
+                draw.vertices.into_iter()
+            ).unwrap();
+            let push_constants = TextShaders::vertex::ty::Constants {
+                transform: draw.transform.into()
+            };
+
+            let font_index = match draw.source {
+                TextDrawSource::Font(index) => index,
+                TextDrawSource::Icon(texture) => {
+                    let pipeline = self.icon_pipelines[depth_tested].clone();
+                    let descriptor_set = PersistentDescriptorSet::start(pipeline.clone(), 0)
+                        .add_sampled_image(texture, self.sdf_sampler.clone()).unwrap()
+                        .build().unwrap();
+
+                    cb = cb.draw(pipeline, &dynamic_state,
+                                 vec![vertex_buffer],
+                                 descriptor_set, push_constants).unwrap();
+                    continue;
+                }
+            };
+            let font = &self.fonts[font_index];
+
+            match text_data.render_mode {
+                TextRenderMode::Bitmap => {
+                    let pipeline = self.vulkan_pipelines[depth_tested].clone();
+                    let descriptor_set = PersistentDescriptorSet::start(pipeline.clone(), 0)
+                        .add_sampled_image(font.cache_texture.clone(), self.sampler.clone()).unwrap()
+                        .build().unwrap();
+
+                    cb = cb.draw(pipeline, &dynamic_state,
+                                 vec![vertex_buffer],
+                                 descriptor_set, push_constants).unwrap();
+                },
+                TextRenderMode::Sdf => {
+                    let pipeline = self.sdf_pipelines[depth_tested].clone();
+                    let effects = &text_data.effects;
+                    // converted to distances in the fragment shader, where the size of a screen pixel is known
+                    let uniform = self.effects_buffer_pool.next(TextShaders::sdf_fragment::ty::Effects {
+                        outline_color: effects.outline_color,
+                        shadow_color: effects.shadow_color,
+                        glow_color: effects.glow_color,
+                        shadow_offset: [effects.shadow_offset.0, effects.shadow_offset.1],
+                        shadow_softness: effects.shadow_softness,
+                        outline_width: effects.outline_width,
+                        glow_width: effects.glow_width,
+                        distance_range: 2.0 * SDF_SPREAD,
+                    }).unwrap();
+
+                    let descriptor_set = PersistentDescriptorSet::start(pipeline.clone(), 0)
+                        .add_sampled_image(font.sdf_atlas.texture.clone(), self.sdf_sampler.clone()).unwrap()
+                        .add_buffer(uniform).unwrap()
+                        .build().unwrap();
+
+                    cb = cb.draw(pipeline, &dynamic_state,
+                                 vec![vertex_buffer],
+                                 descriptor_set, push_constants).unwrap();
+                }
+            }
+        }
+        (cb.end_render_pass().unwrap().build().unwrap(), info.queue_main.clone())
+    }
+}
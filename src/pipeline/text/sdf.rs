@@ -0,0 +1,178 @@
+//! Signed distance field glyph atlas.
+//!
+//! Glyphs are rasterized once at [SDF_BASE_SIZE](constant.SDF_BASE_SIZE.html) and stored as
+//! distance fields, so one atlas renders crisp text at any size, and outlines, shadows and glows
+//! can be derived from the distance in the fragment shader.
+
+
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+use rusttype::{Font, GlyphId, Scale, point};
+use vulkano::buffer::BufferUsage;
+use vulkano::device::Device;
+use vulkano::format::R8Unorm;
+use vulkano::image::AttachmentImage;
+use vulkano::memory::pool::{PotentialDedicatedAllocation, StdMemoryPoolAlloc};
+
+use crate::buffer::CpuAccessibleBufferXalloc;
+use super::CACHE_TEXTURE_USAGE;
+
+
+/// The size of each font's SDF atlas texture, in pixels (i.e. 1024 x 1024)
+pub const SDF_ATLAS_SIZE: u32 = 1024;
+/// Pixel size glyphs are rasterized at before being converted to distance fields.
+pub const SDF_BASE_SIZE: f32 = 48.0;
+/// Largest distance stored in the field, in pixels at `SDF_BASE_SIZE`. Limits outline and glow width.
+pub const SDF_SPREAD: f32 = 8.0;
+
+
+/// Location of a glyph in the atlas.
+#[derive(Clone, Copy, Debug)]
+pub struct SdfGlyph {
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    /// Offset of the quad corners from the glyph origin, in pixels at `SDF_BASE_SIZE`.
+    pub offset_min: [f32; 2],
+    pub offset_max: [f32; 2],
+}
+
+
+pub struct SdfAtlas {
+    /// `None` for glyphs with no outline (e.g. spaces), so they aren't rasterized every frame.
+    glyphs: HashMap<GlyphId, Option<SdfGlyph>>,
+    shelf_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+    full: bool,
+    pub buffer: Arc<CpuAccessibleBufferXalloc<[u8]>>,
+    pub texture: Arc<AttachmentImage<R8Unorm, PotentialDedicatedAllocation<StdMemoryPoolAlloc>>>,
+    /// True if `buffer` has changed since it was last copied to `texture`.
+    pub dirty: bool,
+}
+
+
+impl SdfAtlas {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            glyphs: HashMap::new(),
+            shelf_x: 0,
+            shelf_y: 0,
+            shelf_height: 0,
+            full: false,
+            buffer: CpuAccessibleBufferXalloc::from_iter(device.clone(),
+                                                         BufferUsage::all(),
+                                                         (0 .. SDF_ATLAS_SIZE*SDF_ATLAS_SIZE).map(|_| 0u8)
+            ).expect("failed to create buffer"),
+            texture: AttachmentImage::with_usage(
+                device,
+                [SDF_ATLAS_SIZE, SDF_ATLAS_SIZE],
+                R8Unorm,
+                *CACHE_TEXTURE_USAGE).unwrap(),
+            dirty: false,
+        }
+    }
+
+
+    /// Gets the atlas entry for a glyph, generating it if it isn't in the atlas yet.
+    pub fn glyph(&mut self, font: &Font, id: GlyphId) -> Option<SdfGlyph> {
+        if let Some(entry) = self.glyphs.get(&id) {
+            return *entry;
+        }
+
+        let entry = match generate_sdf(font, id) {
+            Some((width, height, data, offset_min)) => self.insert(width, height, &data, offset_min),
+            None => None
+        };
+        self.glyphs.insert(id, entry);
+        entry
+    }
+
+
+    /// Packs a generated field into the atlas with a simple shelf packer.
+    fn insert(&mut self, width: u32, height: u32, data: &[u8], offset_min: [f32; 2]) -> Option<SdfGlyph> {
+        if self.full {
+            return None;
+        }
+        if self.shelf_x + width > SDF_ATLAS_SIZE {
+            self.shelf_x = 0;
+            self.shelf_y += self.shelf_height + 1;
+            self.shelf_height = 0;
+        }
+        if self.shelf_y + height > SDF_ATLAS_SIZE {
+            warn!(Renderer, "SDF glyph atlas is full, some glyphs won't be drawn");
+            self.full = true;
+            return None;
+        }
+
+        let (x0, y0) = (self.shelf_x, self.shelf_y);
+        {
+            let mut lock = self.buffer.write().unwrap();
+            for y in 0..height {
+                let row = ((y0 + y) * SDF_ATLAS_SIZE + x0) as usize;
+                let src = (y * width) as usize;
+                lock[row..row + width as usize].copy_from_slice(&data[src..src + width as usize]);
+            }
+        }
+        self.dirty = true;
+        self.shelf_x += width + 1;
+        self.shelf_height = self.shelf_height.max(height);
+
+        let size = SDF_ATLAS_SIZE as f32;
+        Some(SdfGlyph {
+            uv_min: [x0 as f32 / size, y0 as f32 / size],
+            uv_max: [(x0 + width) as f32 / size, (y0 + height) as f32 / size],
+            offset_min,
+            offset_max: [offset_min[0] + width as f32, offset_min[1] + height as f32],
+        })
+    }
+}
+
+
+/// Rasterizes a glyph at `SDF_BASE_SIZE` and converts it to a distance field, padded by `SDF_SPREAD`.
+///
+/// Returns the field's dimensions, its data (0.5 on the edge, increasing inwards), and the offset of
+/// its top-left corner from the glyph origin.
+fn generate_sdf(font: &Font, id: GlyphId) -> Option<(u32, u32, Vec<u8>, [f32; 2])> {
+    let glyph = font.glyph(id).scaled(Scale::uniform(SDF_BASE_SIZE)).positioned(point(0.0, 0.0));
+    let bb = glyph.pixel_bounding_box()?;
+    let pad = SDF_SPREAD.ceil() as i32;
+    let width = (bb.width() + pad * 2) as usize;
+    let height = (bb.height() + pad * 2) as usize;
+
+    let mut coverage = vec![0.0f32; width * height];
+    glyph.draw(|x, y, v| {
+        coverage[(y as usize + pad as usize) * width + x as usize + pad as usize] = v;
+    });
+    let inside = |x: i32, y: i32| -> bool {
+        if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+            return false;
+        }
+        coverage[y as usize * width + x as usize] >= 0.5
+    };
+
+    // brute force search for the nearest texel on the other side of the edge. glyphs are small and
+    // only generated once, so this is fast enough.
+    let mut data = vec![0u8; width * height];
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let is_inside = inside(x, y);
+            let mut nearest_sq = (SDF_SPREAD * SDF_SPREAD) as i32 + 1;
+            for dy in -pad..=pad {
+                for dx in -pad..=pad {
+                    let dist_sq = dx * dx + dy * dy;
+                    if dist_sq < nearest_sq && inside(x + dx, y + dy) != is_inside {
+                        nearest_sq = dist_sq;
+                    }
+                }
+            }
+            // the edge is halfway between the two texels
+            let dist = ((nearest_sq as f32).sqrt() - 0.5).min(SDF_SPREAD);
+            let signed = if is_inside { dist } else { -dist };
+            let encoded = (0.5 + signed / (2.0 * SDF_SPREAD)).max(0.0).min(1.0);
+            data[y as usize * width + x as usize] = (encoded * 255.0).round() as u8;
+        }
+    }
+
+    Some((width as u32, height as u32, data, [(bb.min.x - pad) as f32, (bb.min.y - pad) as f32]))
+}
@@ -0,0 +1,115 @@
+//! Complex text shaping and bidirectional reordering, enabled with the `shaping` feature.
+//!
+//! Paragraphs are split into runs by bidi level (UAX #9), and each run is shaped with rustybuzz,
+//! so ligatures, combining marks and contextual forms (e.g. Arabic) render correctly. Glyphs the
+//! primary font can't shape are reshaped with the next font in the fallback chain.
+
+
+use rusttype::{GlyphId, Scale};
+use unicode_bidi::BidiInfo;
+
+use super::FontData;
+use super::layout::{ShapedGlyph, StyleRun, icon_glyph, shape_simple};
+
+
+/// Shapes a paragraph into glyphs in logical order, tagged with their bidi levels and clusters.
+pub(super) fn shape_paragraph(fonts: &[FontData], paragraph: &str, runs: &[StyleRun]) -> Vec<ShapedGlyph> {
+    if paragraph.is_empty() {
+        return Vec::new();
+    }
+    let bidi = BidiInfo::new(paragraph, None);
+
+    let mut glyphs = Vec::new();
+    for run in runs.iter() {
+        if run.icon.is_some() {
+            glyphs.push(icon_glyph(run, bidi.levels[run.range.start].number()));
+            continue;
+        }
+        // split each style run further into runs of one direction
+        let mut start = run.range.start;
+        while start < run.range.end {
+            let level = bidi.levels[start];
+            let mut end = start;
+            while end < run.range.end && bidi.levels[end] == level {
+                end += 1;
+            }
+            let mut shaped = shape_run(fonts, run.chain, 0, run.scale, paragraph, start..end, level.is_rtl());
+            for g in shaped.iter_mut() {
+                g.level = level.number();
+                g.style = run.style;
+            }
+            glyphs.append(&mut shaped);
+            start = end;
+        }
+    }
+    glyphs
+}
+
+
+/// Shapes `range` of `text` with `fonts[chain[chain_pos]]`, reshaping clusters the font has no
+/// glyphs for with the rest of the chain. Returns glyphs in logical order.
+fn shape_run(fonts: &[FontData], chain: &[usize], chain_pos: usize, scale: Scale, text: &str,
+             range: std::ops::Range<usize>, rtl: bool) -> Vec<ShapedGlyph> {
+    let font_index = chain[chain_pos];
+    let font = &fonts[font_index];
+    let face = match rustybuzz::Face::from_slice(&font.bytes, 0) {
+        Some(face) => face,
+        None => {
+            // shouldn't happen for fonts rusttype could load, but still draw something
+            let run = StyleRun { range, chain: &chain[chain_pos..], scale, style: 0, icon: None };
+            return shape_simple(fonts, text, &[run]);
+        }
+    };
+
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(&text[range.clone()]);
+    buffer.set_direction(if rtl { rustybuzz::Direction::RightToLeft } else { rustybuzz::Direction::LeftToRight });
+    let output = rustybuzz::shape(&face, &[], buffer);
+
+    // font units to pixels, the way rusttype scales glyphs: `scale.y` is the height from descent to ascent
+    let v_metrics = font.font.v_metrics_unscaled();
+    let units_y = scale.y / (v_metrics.ascent - v_metrics.descent);
+    let units_x = units_y * scale.x / scale.y;
+    let mut shaped: Vec<ShapedGlyph> = output.glyph_infos().iter().zip(output.glyph_positions().iter())
+        .map(|(info, pos)| {
+            let cluster = range.start + info.cluster as usize;
+            ShapedGlyph {
+                font: font_index,
+                id: GlyphId(info.glyph_id as u16),
+                cluster,
+                advance: pos.x_advance as f32 * units_x,
+                offset: (pos.x_offset as f32 * units_x, -pos.y_offset as f32 * units_y),
+                is_space: text[cluster..].chars().next().map_or(false, |c| c.is_whitespace()),
+                level: 0,
+                style: 0,
+                icon: None,
+            }
+        })
+        .collect();
+    // the shaper outputs right-to-left runs in visual order
+    if rtl {
+        shaped.reverse();
+    }
+    if chain_pos + 1 >= chain.len() {
+        return shaped;
+    }
+
+    // replace runs of missing glyphs with glyphs from the next font
+    let mut glyphs = Vec::with_capacity(shaped.len());
+    let mut i = 0;
+    while i < shaped.len() {
+        if shaped[i].id.0 != 0 {
+            glyphs.push(shaped[i]);
+            i += 1;
+            continue;
+        }
+        let missing_start = i;
+        while i < shaped.len() && shaped[i].id.0 == 0 {
+            i += 1;
+        }
+        let cluster_start = shaped[missing_start..i].iter().map(|g| g.cluster).min().unwrap();
+        let cluster_end = shaped[i..].iter().map(|g| g.cluster).min().unwrap_or(range.end).max(cluster_start);
+        glyphs.append(&mut shape_run(fonts, chain, chain_pos + 1, scale, text, cluster_start..cluster_end, rtl));
+    }
+    glyphs
+}
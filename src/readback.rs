@@ -0,0 +1,118 @@
+//! Reading GPU results back to the CPU without blocking.
+//!
+//! The histogram, Hi-Z pyramid, occlusion frames and picks are all copied into CPU-accessible buffers
+//! and read back frames later, through a [ReadbackRing](struct.ReadbackRing.html).
+
+
+/// Number of readbacks in flight. Results are read back this many frames later at most.
+pub const READBACK_RING_SIZE: usize = 3;
+
+
+/// Ring of buffers GPU results are copied into, read back once the GPU is done with them.
+///
+/// Each slot holds the buffers `B` a result is copied into, and while it's in flight the frame it was
+/// recorded on and `T`, whatever else the result needs to be interpreted (e.g. the view-projection it was
+/// rendered with). If every slot is still in flight, new results are skipped rather than stalling.
+///
+/// `Renderer::submit` currently waits on every frame's fence, so results are always ready on the next poll
+/// and the ring doesn't reduce latency yet. It keeps readbacks from stalling once frames are allowed to overlap.
+pub struct ReadbackRing<B, T> {
+    slots: Vec<B>,
+    pending: Vec<Option<(u64, T)>>,
+    next_slot: usize,
+    /// Frame index of the newest result read back so far.
+    last_read_frame: u64,
+}
+
+
+impl<B, T> ReadbackRing<B, T> {
+    /// Creates a ring of `READBACK_RING_SIZE` slots, with buffers made by `new_slot`.
+    pub fn new<F: FnMut() -> B>(mut new_slot: F) -> Self {
+        Self {
+            slots: (0..READBACK_RING_SIZE).map(|_| new_slot()).collect(),
+            pending: (0..READBACK_RING_SIZE).map(|_| None).collect(),
+            next_slot: 0,
+            last_read_frame: 0,
+        }
+    }
+
+
+    /// Claims the next slot for a result recorded on `frame_index`, and returns the buffers to record the
+    /// copy into. `None` if every slot is still in flight.
+    pub fn begin(&mut self, frame_index: u64, payload: T) -> Option<&mut B> {
+        let slot = self.next_slot;
+        if self.pending[slot].is_some() {
+            return None;
+        }
+        self.pending[slot] = Some((frame_index, payload));
+        self.next_slot = (slot + 1) % READBACK_RING_SIZE;
+        Some(&mut self.slots[slot])
+    }
+
+
+    /// Reads back every finished slot with `read`, which returns `None` while the GPU still holds the
+    /// buffers. Returns the result of the newest frame read back so far, if one was read by this call.
+    pub fn poll<R, F: FnMut(&B, &T) -> Option<R>>(&mut self, mut read: F) -> Option<(u64, T, R)> {
+        let mut newest = None;
+        for slot in 0..READBACK_RING_SIZE {
+            let result = match &self.pending[slot] {
+                Some((_, payload)) => match read(&self.slots[slot], payload) {
+                    Some(result) => result,
+                    // still locked by the GPU, try again next frame
+                    None => continue
+                },
+                None => continue
+            };
+            let (frame, payload) = self.pending[slot].take().unwrap();
+            if frame < self.last_read_frame {
+                continue;
+            }
+            self.last_read_frame = frame;
+            newest = Some((frame, payload, result));
+        }
+        newest
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_results_while_every_slot_is_in_flight() {
+        let mut ring: ReadbackRing<u32, ()> = ReadbackRing::new(|| 0);
+        for frame in 0..READBACK_RING_SIZE as u64 {
+            assert!(ring.begin(frame, ()).is_some());
+        }
+        assert!(ring.begin(READBACK_RING_SIZE as u64, ()).is_none());
+        assert!(ring.poll(|_, _| None::<()>).is_none());
+        assert!(ring.begin(READBACK_RING_SIZE as u64, ()).is_none());
+
+        ring.poll(|_, _| Some(()));
+        assert!(ring.begin(10, ()).is_some());
+    }
+
+    #[test]
+    fn keeps_the_newest_result() {
+        let mut ring: ReadbackRing<u32, &str> = ReadbackRing::new(|| 0);
+        for (slot, frame) in [5u64, 6, 7].iter().enumerate() {
+            *ring.begin(*frame, "payload").unwrap() = slot as u32;
+        }
+        // slot 2 (frame 7) isn't done yet
+        let newest = ring.poll(|buffer, _| if *buffer == 2 { None } else { Some(*buffer) });
+        assert_eq!(newest, Some((6, "payload", 1)));
+        assert_eq!(ring.poll(|buffer, _| Some(*buffer)), Some((7, "payload", 2)));
+        assert_eq!(ring.poll(|buffer, _| Some(*buffer)), None);
+    }
+
+    #[test]
+    fn ignores_results_older_than_the_newest_read() {
+        let mut ring: ReadbackRing<u64, ()> = ReadbackRing::new(|| 0);
+        *ring.begin(1, ()).unwrap() = 1;
+        *ring.begin(2, ()).unwrap() = 2;
+        // frame 1 finishes late
+        assert_eq!(ring.poll(|buffer, _| if *buffer == 2 { Some(*buffer) } else { None }), Some((2, (), 2)));
+        assert_eq!(ring.poll(|buffer, _| Some(*buffer)), None);
+    }
+}
@@ -0,0 +1,300 @@
+//! 3D color grading lookup tables.
+//!
+//! LUTs are authored as `.cube` files (Adobe / Resolve format), or graded from a neutral LUT strip
+//! exported with [write_neutral_lut_strip](fn.write_neutral_lut_strip.html). Both map sRGB-encoded
+//! input to sRGB-encoded output, which is what the tonemapper expects.
+
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use vulkano::device::Queue;
+use vulkano::format::R16G16B16A16Sfloat;
+use vulkano::image::immutable::ImmutableImage;
+
+
+/// Edge length of the built-in neutral LUT.
+pub const NEUTRAL_LUT_SIZE: u32 = 32;
+/// Largest LUT_3D_SIZE allowed by the .cube spec.
+const MAX_LUT_SIZE: u32 = 256;
+
+
+#[derive(Debug)]
+pub enum LutError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    /// A line couldn't be parsed. Contains the (1-based) line number.
+    Parse(usize),
+    /// The file is a 1D LUT, or doesn't declare a LUT_3D_SIZE.
+    MissingSize,
+    UnsupportedSize(u32),
+    /// DOMAIN_MIN / DOMAIN_MAX other than [0, 1].
+    UnsupportedDomain,
+    /// Number of data lines doesn't match LUT_3D_SIZE³.
+    WrongEntryCount { expected: usize, found: usize },
+    /// Strip image isn't `size² x size`.
+    BadStripDimensions(u32, u32),
+}
+
+impl fmt::Display for LutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LutError::Io(e) => write!(f, "failed to read LUT: {}", e),
+            LutError::Image(e) => write!(f, "failed to read LUT strip: {}", e),
+            LutError::Parse(line) => write!(f, "failed to parse LUT at line {}", line),
+            LutError::MissingSize => write!(f, "LUT is missing LUT_3D_SIZE (1D LUTs are unsupported)"),
+            LutError::UnsupportedSize(size) => write!(f, "unsupported LUT size {}", size),
+            LutError::UnsupportedDomain => write!(f, "LUTs with a domain other than [0, 1] are unsupported"),
+            LutError::WrongEntryCount { expected, found } => write!(f, "expected {} LUT entries, found {}", expected, found),
+            LutError::BadStripDimensions(w, h) => write!(f, "LUT strip has invalid dimensions {}x{}", w, h),
+        }
+    }
+}
+
+impl From<std::io::Error> for LutError {
+    fn from(e: std::io::Error) -> Self { LutError::Io(e) }
+}
+
+impl From<image::ImageError> for LutError {
+    fn from(e: image::ImageError) -> Self { LutError::Image(e) }
+}
+
+
+/// A cubic 3D LUT. Entries are ordered red-fastest, then green, then blue, as in .cube files.
+#[derive(Clone, Debug)]
+pub struct CubeLut {
+    pub title: Option<String>,
+    pub size: u32,
+    pub data: Vec<[f32; 3]>,
+}
+
+
+impl CubeLut {
+    /// Creates an identity LUT with the given edge length.
+    pub fn neutral(size: u32) -> CubeLut {
+        let max = (size - 1) as f32;
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push([r as f32 / max, g as f32 / max, b as f32 / max]);
+                }
+            }
+        }
+        CubeLut { title: Some(String::from("neutral")), size, data }
+    }
+
+
+    /// Parses the contents of a .cube file.
+    pub fn parse(source: &str) -> Result<CubeLut, LutError> {
+        let mut title = None;
+        let mut size = None;
+        let mut data = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap();
+            match keyword {
+                "TITLE" => {
+                    title = Some(line["TITLE".len()..].trim().trim_matches('"').to_string());
+                },
+                "LUT_3D_SIZE" => {
+                    let s: u32 = words.next().and_then(|w| w.parse().ok()).ok_or(LutError::Parse(i + 1))?;
+                    if s < 2 || s > MAX_LUT_SIZE {
+                        return Err(LutError::UnsupportedSize(s));
+                    }
+                    size = Some(s);
+                },
+                "LUT_1D_SIZE" => return Err(LutError::MissingSize),
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    for _ in 0..3 {
+                        let v: f32 = words.next().and_then(|w| w.parse().ok()).ok_or(LutError::Parse(i + 1))?;
+                        if v != expected {
+                            return Err(LutError::UnsupportedDomain);
+                        }
+                    }
+                },
+                // other keywords (LUT_3D_INPUT_RANGE, vendor extensions) are ignored
+                _ if keyword.chars().next().map_or(false, |c| c.is_ascii_alphabetic()) => {},
+                _ => {
+                    let r: f32 = keyword.parse().map_err(|_| LutError::Parse(i + 1))?;
+                    let g: f32 = words.next().and_then(|w| w.parse().ok()).ok_or(LutError::Parse(i + 1))?;
+                    let b: f32 = words.next().and_then(|w| w.parse().ok()).ok_or(LutError::Parse(i + 1))?;
+                    data.push([r, g, b]);
+                }
+            }
+        }
+
+        let size = size.ok_or(LutError::MissingSize)?;
+        let expected = (size * size * size) as usize;
+        if data.len() != expected {
+            return Err(LutError::WrongEntryCount { expected, found: data.len() });
+        }
+
+        Ok(CubeLut { title, size, data })
+    }
+
+
+    /// Loads a .cube file from disk.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CubeLut, LutError> {
+        let source = std::fs::read_to_string(path)?;
+        CubeLut::parse(&source)
+    }
+
+
+    /// Loads a LUT from a strip image, as written by
+    /// [write_neutral_lut_strip](fn.write_neutral_lut_strip.html) and graded in an external tool.
+    pub fn load_strip<P: AsRef<Path>>(path: P) -> Result<CubeLut, LutError> {
+        let image = image::open(path)?.to_rgb();
+        let (w, h) = image.dimensions();
+        if h < 2 || h > MAX_LUT_SIZE || w != h * h {
+            return Err(LutError::BadStripDimensions(w, h));
+        }
+
+        let size = h;
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let pixel = image.get_pixel(b * size + r, g);
+                    data.push([pixel[0] as f32 / 255.0, pixel[1] as f32 / 255.0, pixel[2] as f32 / 255.0]);
+                }
+            }
+        }
+
+        Ok(CubeLut { title: None, size, data })
+    }
+
+
+    /// Uploads the LUT to the GPU as a 3D texture.
+    pub fn upload(&self, queue: Arc<Queue>) -> Arc<ImmutableImage<R16G16B16A16Sfloat>> {
+        let image_data: Vec<half::f16> = self.data.iter()
+                                                  .flat_map(|c| vec![c[0], c[1], c[2], 1.0])
+                                                  .map(|f| half::f16::from_f32(f))
+                                                  .collect();
+
+        let (texture, _future) = ImmutableImage::from_iter(
+            image_data.iter().cloned(),
+            vulkano::image::Dimensions::Dim3d { width: self.size, height: self.size, depth: self.size },
+            R16G16B16A16Sfloat,
+            queue).unwrap();
+        texture
+    }
+}
+
+
+/// Writes a neutral LUT as a `size² x size` strip PNG, for grading in an external tool.
+///
+/// Each `size x size` square is one blue slice; red increases to the right, green downwards.
+/// Load the graded result with [CubeLut::load_strip](struct.CubeLut.html#method.load_strip).
+pub fn write_neutral_lut_strip<P: AsRef<Path>>(path: P, size: u32) -> std::io::Result<()> {
+    let max = (size - 1) as f32;
+    let image = image::RgbImage::from_fn(size * size, size, |x, y| {
+        let r = (x % size) as f32 / max;
+        let g = y as f32 / max;
+        let b = (x / size) as f32 / max;
+        image::Rgb([(r * 255.0).round() as u8, (g * 255.0).round() as u8, (b * 255.0).round() as u8])
+    });
+    image.save(path)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL_CUBE: &str = "# Created by hand
+TITLE \"warm\"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0.0 0.0 0.0
+DOMAIN_MAX 1.0 1.0 1.0
+
+0.1 0.0 0.0
+1.0 0.0 0.0
+0.0 1.0 0.0
+  1.0 1.0 0.0
+# comments between entries are fine
+0.0 0.0 1.0
+1.0 0.0 1.0
+0.0 1.0 1.0
+1.0 1.0 0.9
+";
+
+    #[test]
+    fn parse_small_cube() {
+        let lut = CubeLut::parse(SMALL_CUBE).unwrap();
+        assert_eq!(lut.title.as_ref().map(|t| t.as_str()), Some("warm"));
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.data.len(), 8);
+        assert_eq!(lut.data[0], [0.1, 0.0, 0.0]);
+        assert_eq!(lut.data[3], [1.0, 1.0, 0.0]);
+        assert_eq!(lut.data[7], [1.0, 1.0, 0.9]);
+    }
+
+    #[test]
+    fn parse_ignores_unknown_keywords() {
+        let source = SMALL_CUBE.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0.0 1.0");
+        assert_eq!(CubeLut::parse(&source).unwrap().size, 2);
+    }
+
+    /// The error parsing `source` fails with.
+    fn parse_error(source: &str) -> LutError {
+        CubeLut::parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn parse_malformed_lines() {
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("  1.0 1.0 0.0", "1.0 x 0.0")), LutError::Parse(10)));
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("  1.0 1.0 0.0", "1.0 1.0")), LutError::Parse(10)));
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE two")), LutError::Parse(3)));
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("DOMAIN_MIN 0.0 0.0 0.0", "DOMAIN_MIN 0.0 0.0")), LutError::Parse(4)));
+    }
+
+    #[test]
+    fn parse_size() {
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("LUT_3D_SIZE 2\n", "")), LutError::MissingSize));
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("LUT_3D_SIZE 2", "LUT_1D_SIZE 2")), LutError::MissingSize));
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 1")), LutError::UnsupportedSize(1)));
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 3")),
+                         LutError::WrongEntryCount { expected: 27, found: 8 }));
+    }
+
+    #[test]
+    fn parse_domain() {
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("DOMAIN_MAX 1.0 1.0 1.0", "DOMAIN_MAX 1.0 2.0 1.0")), LutError::UnsupportedDomain));
+        assert!(matches!(parse_error(&SMALL_CUBE.replace("DOMAIN_MIN 0.0 0.0 0.0", "DOMAIN_MIN -0.5 0.0 0.0")), LutError::UnsupportedDomain));
+    }
+
+    #[test]
+    fn neutral_strip_round_trip() {
+        let path = std::env::temp_dir().join(format!("phosphor_lut_strip_{}.png", std::process::id()));
+        write_neutral_lut_strip(&path, 4).unwrap();
+        let lut = CubeLut::load_strip(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let lut = lut.unwrap();
+        let neutral = CubeLut::neutral(4);
+        assert_eq!(lut.size, 4);
+        for (loaded, expected) in lut.data.iter().zip(neutral.data.iter()) {
+            for c in 0..3 {
+                assert!((loaded[c] - expected[c]).abs() < 1.0 / 255.0);
+            }
+        }
+    }
+
+    #[test]
+    fn strip_dimensions() {
+        let path = std::env::temp_dir().join(format!("phosphor_lut_bad_strip_{}.png", std::process::id()));
+        image::RgbImage::new(10, 4).save(&path).unwrap();
+        let lut = CubeLut::load_strip(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(lut.unwrap_err(), LutError::BadStripDimensions(10, 4)));
+    }
+}
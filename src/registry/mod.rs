@@ -1,20 +1,31 @@
 //! Global registry types.
 
 
+pub mod lut;
+
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::path::Path;
 
-use vulkano::format::{R8G8B8A8Srgb, R16G16B16A16Sfloat};
+use vulkano::format::{B8G8R8A8Srgb, R8G8B8A8Srgb, R16G16B16A16Sfloat};
+use vulkano::image::attachment::AttachmentImage;
 use vulkano::image::immutable::ImmutableImage;
 use vulkano::device::Queue;
 use std::io::BufReader;
 
+use parking_lot::RwLock;
+
+use self::lut::{CubeLut, LutError, NEUTRAL_LUT_SIZE};
+
 
 /// Global texture registry.
 pub struct TextureRegistry {
     ldr_textures: HashMap<String, Arc<ImmutableImage<R8G8B8A8Srgb>>>,
-    hdr_textures: HashMap<String, Arc<ImmutableImage<R16G16B16A16Sfloat>>>
+    hdr_textures: HashMap<String, Arc<ImmutableImage<R16G16B16A16Sfloat>>>,
+    /// 3D color grading LUTs. Behind a lock so they can be (re)loaded at runtime.
+    luts: RwLock<HashMap<String, Arc<ImmutableImage<R16G16B16A16Sfloat>>>>,
+    /// Color targets of offscreen views, see [RenderView](::view::RenderView). Registered and removed by the renderer.
+    render_targets: RwLock<HashMap<String, Arc<AttachmentImage<B8G8R8A8Srgb>>>>
 }
 
 
@@ -23,6 +34,8 @@ impl TextureRegistry {
         TextureRegistry {
             ldr_textures: HashMap::new(),
             hdr_textures: HashMap::new(),
+            luts: RwLock::new(HashMap::new()),
+            render_targets: RwLock::new(HashMap::new()),
         }
     }
 
@@ -92,6 +105,35 @@ impl TextureRegistry {
             };
             self.hdr_textures.insert(name.to_string(), texture);
         }
+
+        self.insert_lut("neutral", &CubeLut::neutral(NEUTRAL_LUT_SIZE), queue.clone());
+        if let Ok(entries) = std::fs::read_dir("textures/luts/") {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if path.extension().map_or(true, |ext| ext != "cube") {
+                    continue;
+                }
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                if let Err(e) = self.load_lut(&name, &path, queue.clone()) {
+                    warn!(Renderer, "Failed to load LUT {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+
+
+    /// Loads a .cube file from disk and registers it as a color grading LUT with the given name.
+    pub fn load_lut<P: AsRef<Path>>(&self, name: &str, path: P, queue: Arc<Queue>) -> Result<(), LutError> {
+        let lut = CubeLut::load(path)?;
+        self.insert_lut(name, &lut, queue);
+        Ok(())
+    }
+
+
+    /// Uploads a LUT and registers it with the given name, replacing any existing LUT with that name.
+    pub fn insert_lut(&self, name: &str, lut: &CubeLut, queue: Arc<Queue>) {
+        let texture = lut.upload(queue);
+        self.luts.write().insert(name.to_string(), texture);
     }
 
 
@@ -109,4 +151,27 @@ impl TextureRegistry {
             None => None
         }
     }
+
+
+    /// Gets a handle to the color grading LUT with the given name, or None if one couldn't be found.
+    pub fn get_lut(&self, name: &str) -> Option<Arc<ImmutableImage<R16G16B16A16Sfloat>>> {
+        self.luts.read().get(name).cloned()
+    }
+
+
+    /// Registers the color target of an offscreen view, replacing any existing target with that name.
+    pub fn insert_render_target(&self, name: &str, target: Arc<AttachmentImage<B8G8R8A8Srgb>>) {
+        self.render_targets.write().insert(name.to_string(), target);
+    }
+
+
+    pub fn remove_render_target(&self, name: &str) {
+        self.render_targets.write().remove(name);
+    }
+
+
+    /// Gets a handle to the offscreen view target with the given name, or None if one couldn't be found.
+    pub fn get_render_target(&self, name: &str) -> Option<Arc<AttachmentImage<B8G8R8A8Srgb>>> {
+        self.render_targets.read().get(name).cloned()
+    }
 }
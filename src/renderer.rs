@@ -1,44 +1,60 @@
 //! Main renderer.
 
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
 
-use cgmath::{EuclideanSpace, Matrix4, Vector4, SquareMatrix, Deg};
+use cgmath::{EuclideanSpace, Matrix4, Point3, Vector3, Vector4, SquareMatrix, Deg};
 use winit::{Window, WindowBuilder, EventsLoop, MouseCursor};
 use winit::dpi::LogicalSize;
 
-use vulkano::buffer::BufferUsage;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, AutoCommandBuffer};
 use vulkano::device::{Device, DeviceExtensions, Queue};
-use vulkano::format::{D32Sfloat, R16G16B16A16Sfloat, R32Uint, R32Sint};
+use vulkano::format::{ClearValue, D32Sfloat, R16G16B16A16Sfloat, R16G16Sfloat, R32Sfloat, R32Uint};
 use vulkano::image::attachment::AttachmentImage;
 use vulkano::image::swapchain::SwapchainImage;
+use vulkano::image::ImageViewAccess;
 use vulkano::instance::{Instance, PhysicalDevice};
 use vulkano::swapchain::{Swapchain, Surface, SwapchainCreationError, SwapchainAcquireFuture};
 use vulkano::sync::GpuFuture;
 use vulkano::image::ImageUsage;
+use vulkano::pipeline::depth_stencil::{DepthStencil, Compare};
+use vulkano::sampler::Filter;
 use toolbox::Transform;
 
 use crate::camera::Camera;
 use crate::geometry::{VertexGroup, Material, VertexPositionObjectId, DeferredShadingVertex};
 use crate::registry::TextureRegistry;
-use crate::pipeline::{RenderPipelineAbstract, DeferredShadingRenderPipeline, DeferredLightingRenderPipeline, LinesRenderPipeline, TextRenderPipeline, OcclusionRenderPipeline, PostProcessRenderPipeline};
-use crate::buffer::CpuAccessibleBufferXalloc;
+use crate::pipeline::{RenderPipelineAbstract, DeferredShadingRenderPipeline, DeferredLightingRenderPipeline, LinesRenderPipeline, TextRenderPipeline, OcclusionRenderPipeline, PickingRenderPipeline, PostProcessRenderPipeline, TemporalAARenderPipeline};
 use crate::geometry::VertexPositionColorAlpha;
 use crate::pipeline::text::TextData;
-use crate::pipeline::occlusion::OCCLUSION_FRAME_SIZE;
+use crate::pipeline::occlusion::{OCCLUSION_FRAME_SIZE, OcclusionReadback, OcclusionResults};
+use crate::pipeline::picking::PickResult;
+use crate::pipeline::temporal_aa::{halton, TAA_JITTER_SEQUENCE_LENGTH};
 use crate::vulkano_win::VkSurfaceBuild;
 use crate::pipeline::imgui::ImguiRenderPipeline;
-use crate::compute::HistogramCompute;
-use std::sync::atomic::Ordering;
+use crate::compute::{HistogramCompute, HiZCompute};
+use crate::debug_draw::DebugDraw;
+use crate::view::{RenderView, ViewportRect};
+use crate::graph::{self, RenderGraph, RenderGraphError, PassDesc, InsertionPoint, GraphAttachments, SWAPCHAIN, VIEW_TARGETS};
 use parking_lot::Mutex;
 
 
 /// Matrix to correct vulkan clipping planes and flip y axis.
 /// See [https://matthewwellings.com/blog/the-new-vulkan-coordinate-system/](https://matthewwellings.com/blog/the-new-vulkan-coordinate-system/).
+///
+/// cgmath matrices are column-major, so each `Vector4` here is a column.
 pub static VULKAN_CORRECT_CLIP: Matrix4<f32> = Matrix4 {
     x: Vector4 { x: 1.0, y:  0.0, z: 0.0, w: 0.0 },
     y: Vector4 { x: 0.0, y: -1.0, z: 0.0, w: 0.0 },
-    z: Vector4 { x: 0.0, y:  0.0, z: 0.5, w: 0.5 },
-    w: Vector4 { x: 0.0, y:  0.0, z: 0.0, w: 1.0 }
+    z: Vector4 { x: 0.0, y:  0.0, z: 0.5, w: 0.0 },
+    w: Vector4 { x: 0.0, y:  0.0, z: 0.5, w: 1.0 }
+};
+/// Like `VULKAN_CORRECT_CLIP`, but maps the near plane to depth 1 and the far plane to depth 0.
+pub static VULKAN_REVERSE_Z_CLIP: Matrix4<f32> = Matrix4 {
+    x: Vector4 { x: 1.0, y:  0.0, z:  0.0, w: 0.0 },
+    y: Vector4 { x: 0.0, y: -1.0, z:  0.0, w: 0.0 },
+    z: Vector4 { x: 0.0, y:  0.0, z: -0.5, w: 0.0 },
+    w: Vector4 { x: 0.0, y:  0.0, z:  0.5, w: 1.0 }
 };
 
 
@@ -52,38 +68,57 @@ pub const DEBUG_VISUALIZE_DIFFUSE_LIGHTING_ONLY: u32 = 6;
 pub const DEBUG_VISUALIZE_SPECULAR_LIGHTING_ONLY: u32 = 7;
 pub const DEBUG_VISUALIZE_NO_POST_PROCESSING: u32 = 8;
 pub const DEBUG_VISUALIZE_OCCLUSION_BUFFER: u32 = 9;
-pub const DEBUG_VISUALIZE_MAX: u32 = 10;
+pub const DEBUG_VISUALIZE_VELOCITY_BUFFER: u32 = 10;
+pub const DEBUG_VISUALIZE_AMBIENT_OCCLUSION: u32 = 11;
+pub const DEBUG_VISUALIZE_MAX: u32 = 12;
 
 
 lazy_static! {
     static ref GBUFFER_USAGE: ImageUsage = ImageUsage {
         color_attachment: true,
         input_attachment: true,
+        sampled: true,
         ..ImageUsage::none()
     };
     static ref LUMA_BUFFER_USAGE: ImageUsage = ImageUsage {
         color_attachment: true,
         input_attachment: true,
-        transfer_source: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+    /// Not transient, since lines and world-space text are depth tested against it after the deferred passes.
+    static ref DEPTH_BUFFER_USAGE: ImageUsage = ImageUsage {
+        depth_stencil_attachment: true,
+        ..ImageUsage::none()
+    };
+    static ref AO_BUFFER_USAGE: ImageUsage = ImageUsage {
+        storage: true,
+        sampled: true,
         transfer_destination: true,
         ..ImageUsage::none()
     };
 }
 
 
-fn recreate_attachments(device: Arc<Device>, dimensions: [u32; 2], old_occlusion: Option<Arc<AttachmentImage<R32Uint>>>) -> RendererAttachments {
+pub(crate) fn recreate_attachments(device: Arc<Device>, dimensions: [u32; 2], old_occlusion: Option<Arc<AttachmentImage<R32Uint>>>) -> RendererAttachments {
     RendererAttachments {
         position:     AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
         normal:       AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
         albedo:       AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
         roughness:    AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
         metallic:     AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
+        velocity:     AttachmentImage::with_usage(device.clone(), dimensions, R16G16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
+        ao:           AttachmentImage::with_usage(device.clone(), dimensions, R32Sfloat, AO_BUFFER_USAGE.clone()).unwrap(),
+        ao_scratch:   AttachmentImage::with_usage(device.clone(), dimensions, R32Sfloat, AO_BUFFER_USAGE.clone()).unwrap(),
         hdr_diffuse:  AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
         hdr_specular: AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
         scene_color:  AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
-        main_depth:   AttachmentImage::transient(device.clone(), dimensions, D32Sfloat).unwrap(),
-        luma_render:  AttachmentImage::with_usage(device.clone(), dimensions, R32Sint, LUMA_BUFFER_USAGE.clone()).unwrap(),
-        luma_mips:    AttachmentImage::with_usage(device.clone(), [512, 512], R32Sint, LUMA_BUFFER_USAGE.clone()).unwrap(),
+        taa_history: [
+            AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
+            AttachmentImage::with_usage(device.clone(), dimensions, R16G16B16A16Sfloat, GBUFFER_USAGE.clone()).unwrap(),
+        ],
+        main_depth:   AttachmentImage::with_usage(device.clone(), dimensions, D32Sfloat, DEPTH_BUFFER_USAGE.clone()).unwrap(),
+        luma_render:  AttachmentImage::with_usage(device.clone(), dimensions, R32Sfloat, LUMA_BUFFER_USAGE.clone()).unwrap(),
         occlusion: old_occlusion
     }
 }
@@ -104,12 +139,20 @@ pub struct RendererAttachments {
     pub albedo: Arc<AttachmentImage<R16G16B16A16Sfloat>>,
     pub roughness: Arc<AttachmentImage<R16G16B16A16Sfloat>>,
     pub metallic: Arc<AttachmentImage<R16G16B16A16Sfloat>>,
+    /// Screen-space motion vectors, in UV units (current - previous).
+    pub velocity: Arc<AttachmentImage<R16G16Sfloat>>,
+    /// Blurred ambient occlusion term, 1.0 = unoccluded.
+    pub ao: Arc<AttachmentImage<R32Sfloat>>,
+    /// Intermediate buffer for the separable AO blur.
+    pub ao_scratch: Arc<AttachmentImage<R32Sfloat>>,
     pub hdr_diffuse: Arc<AttachmentImage<R16G16B16A16Sfloat>>,
     pub hdr_specular: Arc<AttachmentImage<R16G16B16A16Sfloat>>,
     pub scene_color: Arc<AttachmentImage<R16G16B16A16Sfloat>>,
+    /// Ping-pong TAA history buffers. The buffer at `frame_index % 2` holds the resolved color for the current frame.
+    pub taa_history: [Arc<AttachmentImage<R16G16B16A16Sfloat>>; 2],
     pub main_depth: Arc<AttachmentImage<D32Sfloat>>,
-    pub luma_render: Arc<AttachmentImage<R32Sint>>,
-    pub luma_mips: Arc<AttachmentImage<R32Sint>>,
+    /// Per-pixel scene luminance, read directly by the histogram compute.
+    pub luma_render: Arc<AttachmentImage<R32Sfloat>>,
     pub occlusion: Option<Arc<AttachmentImage<R32Uint>>>
 }
 
@@ -124,14 +167,35 @@ pub struct RenderInfo {
     pub queue_compute: Arc<Queue>,
 
     pub image_num: usize,
+    /// Number of frames drawn so far. Used for TAA jitter and history ping-ponging.
+    pub frame_index: u64,
     pub dimensions: [u32; 2],
     pub camera_transform: Transform,
     pub view_mat: Matrix4<f32>,
+    /// Projection matrix, including TAA jitter.
     pub proj_mat: Matrix4<f32>,
-    pub fov: Deg<f32>,
+    /// Projection matrix without TAA jitter.
+    pub unjittered_proj_mat: Matrix4<f32>,
+    /// View matrix from the previous frame.
+    pub prev_view_mat: Matrix4<f32>,
+    /// Unjittered projection matrix from the previous frame.
+    pub prev_proj_mat: Matrix4<f32>,
+    /// Sub-pixel jitter applied to `proj_mat` this frame, in pixels.
+    pub jitter: [f32; 2],
+    /// Camera the frame is drawn with.
+    pub camera: Camera,
+    /// Fixed when the renderer is created.
+    pub depth_mode: DepthMode,
     pub tonemapping_info: TonemappingInfo,
-    pub luma_avg_buffer: Arc<CpuAccessibleBufferXalloc<[u16]>>,
+    pub color_grading_info: ColorGradingInfo,
+    pub taa_info: TemporalAAInfo,
+    pub ao_info: AmbientOcclusionInfo,
+    pub lines_info: LinesInfo,
+    pub occlusion_info: OcclusionInfo,
+    pub occlusion_culling_info: OcclusionCullingInfo,
     pub histogram_compute: Arc<Mutex<HistogramCompute>>,
+    pub occlusion_readback: Arc<Mutex<OcclusionReadback>>,
+    pub hiz_compute: Arc<Mutex<HiZCompute>>,
 
     pub tex_registry: Arc<TextureRegistry>,
 
@@ -139,47 +203,358 @@ pub struct RenderInfo {
 
     pub render_queues: Arc<RwLock<RenderQueues>>,
 
+    /// Attachments allocated by the render graph for the passes that create them.
+    pub graph_attachments: GraphAttachments,
+
     pub debug_visualize_setting: u32,
+
+    /// Name of the [RenderView](::view::RenderView) being drawn, `None` for the main view.
+    pub view_name: Option<String>,
+}
+
+
+impl RenderInfo {
+    /// Moves the current matrices to the previous frame's, and updates them for a camera at `transform`.
+    /// `frame_index` and `dimensions` must already be set for the new frame.
+    pub(crate) fn update_view(&mut self, camera: &Camera, transform: &Transform) {
+        self.prev_view_mat = self.view_mat;
+        self.prev_proj_mat = self.unjittered_proj_mat;
+
+        self.view_mat = Matrix4::from(transform.rotation) * Matrix4::from_translation((transform.position * -1.0).to_vec());
+        self.unjittered_proj_mat = camera.projection_matrix(self.dimensions[0] as f32 / self.dimensions[1] as f32, self.depth_mode);
+
+        // sub-pixel jitter for TAA, offset in clip space so it's constant in screen space
+        self.jitter = if self.taa_info.enabled {
+            let i = (self.frame_index % TAA_JITTER_SEQUENCE_LENGTH as u64) as u32 + 1;
+            [halton(i, 2) - 0.5, halton(i, 3) - 0.5]
+        }
+        else {
+            [0.0, 0.0]
+        };
+        let jitter_offset = Vector3::new(self.jitter[0] * 2.0 / self.dimensions[0] as f32,
+                                         self.jitter[1] * 2.0 / self.dimensions[1] as f32,
+                                         0.0);
+        self.proj_mat = Matrix4::from_translation(jitter_offset) * self.unjittered_proj_mat;
+
+        self.camera = camera.clone();
+        self.camera_transform = transform.clone();
+    }
 }
 
 
-pub enum GestaltRenderPass {
-    Occlusion        = 0,
-    DeferredShading  = 1,
-    DeferredLighting = 2,
-    PostProcess      = 3,
-    Lines            = 4,
-    Text             = 5,
-    Imgui            = 6,
+/// Tonemapping curve used to map exposed HDR color to the display.
+///
+/// Values match the `TONEMAP_*` constants in `tonemapping.inc`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TonemapOperator {
+    /// No curve, just clamps to [0, 1].
+    LinearClamp      = 0,
+    /// Reinhard, extended with a white point so values above it burn out to white.
+    ReinhardExtended = 1,
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable            = 2,
+    /// Stephen Hill's fit of the ACES RRT + ODT. Ignores `white_point`.
+    AcesFitted       = 3,
+    /// Minimal AgX. Ignores `white_point`.
+    AgX              = 4,
+}
+
+
+/// How the luminance histogram weights pixels when metering exposure.
+///
+/// Values match the `METERING_*` constants in `histogram_bin.comp`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeteringMode {
+    /// Every pixel counts equally.
+    Average        = 0,
+    /// Pixels near the center count more, falling off towards `metering_radius`.
+    CenterWeighted = 1,
+    /// Only pixels within `metering_radius` of the center count.
+    Spot           = 2,
 }
 
 
 #[derive(Clone)]
 pub struct TonemappingInfo {
-    pub adjust_speed: f32,
+    /// If false, `exposure` is left alone and can be set manually.
+    pub auto_exposure: bool,
+    /// Adaptation rate (per second) when the scene gets darker and exposure increases.
+    pub adapt_speed_brighten: f32,
+    /// Adaptation rate (per second) when the scene gets brighter and exposure decreases.
+    pub adapt_speed_darken: f32,
+    /// Fraction of pixels below the low end of the metered luminance range, in [0, 1].
+    pub low_percentile: f32,
+    /// Fraction of pixels below the high end of the metered luminance range, in [0, 1].
+    pub high_percentile: f32,
+    pub metering_mode: MeteringMode,
+    /// Radius of the metering area, relative to half the screen height.
+    pub metering_radius: f32,
     pub hist_low_percentile_bin: f32,
     pub hist_high_percentile_bin: f32,
     pub avg_scene_luma: f32,
+    /// EV100 metered from the latest histogram, before adaptation.
+    pub target_ev100: f32,
+    /// Adapted EV100 the current exposure is derived from.
     pub scene_ev100: f32,
     pub exposure: f32,
+    /// Bias added to the adapted EV100. Positive values darken the image.
     pub exposure_adjustment: f32,
-    pub min_exposure: f32,
-    pub max_exposure: f32,
-    pub vignette_opacity: f32
+    /// Additional bias as a function of adapted EV100, as `[ev100, bias]` points sorted by EV100.
+    /// Linearly interpolated and clamped at the ends. Empty means no bias.
+    pub compensation_curve: Vec<[f32; 2]>,
+    pub min_ev100: f32,
+    pub max_ev100: f32,
+    pub vignette_opacity: f32,
+    pub operator: TonemapOperator,
+    /// Exposed linear value that maps to display white, for operators that support it.
+    pub white_point: f32
 }
 impl Default for TonemappingInfo {
     fn default() -> Self {
         Self {
-            adjust_speed: 0.5,
+            auto_exposure: true,
+            adapt_speed_brighten: 1.5,
+            adapt_speed_darken: 3.0,
+            low_percentile: 0.6,
+            high_percentile: 0.9,
+            metering_mode: MeteringMode::CenterWeighted,
+            metering_radius: 1.0,
             hist_low_percentile_bin: 0.0,
             hist_high_percentile_bin: 127.0,
             avg_scene_luma: 1.0,
+            target_ev100: 0.0,
             scene_ev100: 0.0,
             exposure: 0.5,
-            min_exposure: 0.1,
-            max_exposure: 3.0,
             exposure_adjustment: 0.0,
-            vignette_opacity: 0.2
+            compensation_curve: Vec::new(),
+            min_ev100: -4.0,
+            max_ev100: 16.0,
+            vignette_opacity: 0.2,
+            operator: TonemapOperator::AcesFitted,
+            white_point: 11.2
+        }
+    }
+}
+impl TonemappingInfo {
+    /// Evaluates `compensation_curve` at the given EV100.
+    pub fn compensation(&self, ev100: f32) -> f32 {
+        let curve = &self.compensation_curve;
+        if curve.is_empty() {
+            return 0.0;
+        }
+        if ev100 <= curve[0][0] {
+            return curve[0][1];
+        }
+        for pair in curve.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if ev100 <= b[0] {
+                let t = if b[0] > a[0] { (ev100 - a[0]) / (b[0] - a[0]) } else { 1.0 };
+                return a[1] + (b[1] - a[1]) * t;
+            }
+        }
+        curve[curve.len() - 1][1]
+    }
+}
+
+
+#[derive(Clone)]
+pub struct TemporalAAInfo {
+    /// If false, jitter is disabled and the resolve pass passes the current frame through.
+    pub enabled: bool,
+    /// Weight of the reprojected history when blending with the current frame.
+    pub history_weight: f32,
+}
+impl Default for TemporalAAInfo {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            history_weight: 0.9
+        }
+    }
+}
+
+
+/// How lines are rasterized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineRenderMode {
+    /// 1 pixel hardware lines. Fastest, but aliased, and ignore line widths.
+    Hardware,
+    /// Lines expanded to screen-space quads, with widths, anti-aliasing and dashing.
+    Wide,
+}
+
+
+#[derive(Clone)]
+pub struct LinesInfo {
+    pub mode: LineRenderMode,
+    /// Width in pixels of lines that don't set their own width. Only used in `LineRenderMode::Wide`.
+    pub width: f32,
+    /// Width in pixels of the anti-aliased falloff at line edges.
+    pub feather: f32,
+    /// Length in pixels of each dash, 0.0 = solid lines. Dashes restart at every segment.
+    pub dash_length: f32,
+    /// Length in pixels of the gaps between dashes.
+    pub gap_length: f32,
+}
+impl Default for LinesInfo {
+    fn default() -> Self {
+        Self {
+            mode: LineRenderMode::Hardware,
+            width: 1.5,
+            feather: 1.0,
+            dash_length: 0.0,
+            gap_length: 4.0,
+        }
+    }
+}
+
+
+/// How depth is stored in depth buffers. Pipelines are built for it, so it's fixed when the renderer is created.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthMode {
+    /// Depth 0 at the near plane and 1 at the far plane.
+    Standard,
+    /// Depth 1 at the near plane and 0 at infinity. Float precision is spread evenly over distance, so
+    /// perspective projections use an infinite far plane without z-fighting. `Camera::far` is ignored
+    /// by perspective projections in this mode.
+    ReverseZ,
+}
+impl DepthMode {
+    /// Depth of the near plane.
+    pub fn near_depth(self) -> f32 {
+        match self {
+            DepthMode::Standard => 0.0,
+            DepthMode::ReverseZ => 1.0,
+        }
+    }
+
+    /// Depth buffer clear value, i.e. the depth of the far plane.
+    pub fn clear_depth(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+
+    /// Converts a comparison written for standard depth, e.g. `Compare::Less` for "nearer", to this mode.
+    pub fn compare(self, compare: Compare) -> Compare {
+        match (self, compare) {
+            (DepthMode::ReverseZ, Compare::Less) => Compare::Greater,
+            (DepthMode::ReverseZ, Compare::LessOrEqual) => Compare::GreaterOrEqual,
+            (DepthMode::ReverseZ, Compare::Greater) => Compare::Less,
+            (DepthMode::ReverseZ, Compare::GreaterOrEqual) => Compare::LessOrEqual,
+            (_, compare) => compare
+        }
+    }
+
+    /// Depth test and write for opaque geometry.
+    pub fn depth_test(self) -> DepthStencil {
+        DepthStencil {
+            depth_compare: match self {
+                DepthMode::Standard => Compare::Less,
+                DepthMode::ReverseZ => Compare::GreaterOrEqual,
+            },
+            ..DepthStencil::simple_depth_test()
+        }
+    }
+
+    /// Matrix from OpenGL style clip space to Vulkan clip space, with this mode's depth direction.
+    pub fn clip_correction(self) -> Matrix4<f32> {
+        match self {
+            DepthMode::Standard => VULKAN_CORRECT_CLIP,
+            DepthMode::ReverseZ => VULKAN_REVERSE_Z_CLIP,
+        }
+    }
+}
+
+
+/// Projection used by the occlusion pass.
+#[derive(Clone, Copy, Debug)]
+pub enum OcclusionCamera {
+    /// The main camera's projection, so visibility matches what's on screen.
+    MatchCamera,
+    /// The main camera's projection with a different vertical field of view.
+    Fov(Deg<f32>),
+    /// The main camera's frustum widened by a fraction on each side, e.g. 0.1 for 10%, so objects just
+    /// off screen are already known to be visible when the camera turns.
+    GuardBand(f32),
+}
+
+
+/// Occlusion pass settings.
+#[derive(Clone)]
+pub struct OcclusionInfo {
+    pub camera: OcclusionCamera,
+    /// Size of the occlusion frame. Changing it recreates the occlusion pass and drops results in flight.
+    pub resolution: [u32; 2],
+}
+impl Default for OcclusionInfo {
+    fn default() -> Self {
+        Self {
+            camera: OcclusionCamera::MatchCamera,
+            resolution: OCCLUSION_FRAME_SIZE,
+        }
+    }
+}
+
+
+/// Hi-Z occlusion culling of meshes against the occlusion pass. See [HiZCompute](../compute/struct.HiZCompute.html).
+///
+/// Only meshes with `MeshRenderQueueEntry::bounds` are culled, and only by geometry in the occluder queue.
+#[derive(Clone)]
+pub struct OcclusionCullingInfo {
+    pub enabled: bool,
+}
+impl Default for OcclusionCullingInfo {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+        }
+    }
+}
+
+
+/// Color grading settings. LUTs are looked up by name in the [TextureRegistry](../registry/struct.TextureRegistry.html).
+#[derive(Clone)]
+pub struct ColorGradingInfo {
+    pub enabled: bool,
+    pub lut_a: String,
+    pub lut_b: String,
+    /// Blend weight between the two LUTs, 0.0 = only `lut_a`, 1.0 = only `lut_b`.
+    pub blend: f32,
+}
+impl Default for ColorGradingInfo {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            lut_a: String::from("neutral"),
+            lut_b: String::from("neutral"),
+            blend: 0.0
+        }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct AmbientOcclusionInfo {
+    pub enabled: bool,
+    /// Sampling radius, in world units.
+    pub radius: f32,
+    /// Strength of the occlusion term. 1.0 is physically plausible-ish, higher values exaggerate it.
+    pub intensity: f32,
+    /// Angle bias (as a cosine), to avoid self-occlusion on flat or low-tessellation surfaces.
+    pub bias: f32,
+    /// How strongly the blur avoids smearing across depth discontinuities.
+    pub blur_sharpness: f32,
+}
+impl Default for AmbientOcclusionInfo {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 1.0,
+            intensity: 1.0,
+            bias: 0.1,
+            blur_sharpness: 8.0
         }
     }
 }
@@ -191,6 +566,8 @@ pub struct RenderQueues {
     pub meshes: Vec<MeshRenderQueueEntry>,
     pub lines: LineRenderQueue,
     pub text: Vec<TextData>,
+    /// Debug shapes, drawn with the lines and text.
+    pub debug: DebugDraw,
 }
 
 
@@ -198,7 +575,13 @@ pub struct RenderQueues {
 pub struct MeshRenderQueueEntry {
     pub vertex_group: Arc<VertexGroup<DeferredShadingVertex>>,
     pub material: Material,
-    pub transform: Matrix4<f32>
+    pub transform: Matrix4<f32>,
+    /// Transform from the previous frame, used to generate motion vectors.
+    pub prev_transform: Matrix4<f32>,
+    /// ID reported by `Renderer::pick`. 0 if the mesh can't be picked.
+    pub object_id: u32,
+    /// Object space bounding box, for occlusion culling. `None` if the mesh is never culled.
+    pub bounds: Option<(Point3<f32>, Point3<f32>)>,
 }
 
 
@@ -211,7 +594,6 @@ pub struct LineRenderQueue {
 /// Render queue for the occlusion pass.
 pub struct OcclusionRenderQueue {
     pub vertex_group: Arc<VertexGroup<VertexPositionObjectId>>,
-    pub output_cpu_buffer: Arc<CpuAccessibleBufferXalloc<[u32]>>,
 }
 
 
@@ -223,19 +605,35 @@ pub struct Renderer {
     swapchain: Arc<Swapchain<Window>>,
     /// Swapchain images.
     images: Vec<Arc<SwapchainImage<Window>>>,
+    /// Swapchain images, as framebuffer attachments.
+    targets: Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
     /// If true, swapchain needs to be recreated.
     recreate_swapchain: bool,
-    /// List of render pipelines.
-    pipelines: Vec<Box<dyn RenderPipelineAbstract>>,
+    /// Passes drawing the main view.
+    graph: RenderGraph,
     /// Information required by render pipelines
     pub info: RenderInfo,
-    imgui_pipeline: Option<ImguiRenderPipeline>
+    /// Stored outside of `graph` so fonts can be registered at runtime.
+    text_pipeline: TextRenderPipeline,
+    /// Stored outside of `graph` so picks can be requested.
+    picking_pipeline: PickingRenderPipeline,
+    imgui_pipeline: Option<ImguiRenderPipeline>,
+    /// Offscreen views, drawn before the main view.
+    views: Vec<RenderView>,
+    /// False until the first histogram result has been metered.
+    exposure_initialized: bool
 }
 
 
 impl Renderer {
-    /// Creates a new `Renderer`.
+    /// Creates a new `Renderer`. No fonts ship with the crate, so register one under
+    /// `pipeline::text::DEFAULT_FONT_FAMILY` with [text_pipeline_mut](#method.text_pipeline_mut) before queueing text.
     pub fn new(event_loop: &EventsLoop) -> Renderer {
+        Renderer::new_with_depth_mode(event_loop, DepthMode::Standard)
+    }
+
+    /// Creates a new `Renderer` with the given depth mode, e.g. `DepthMode::ReverseZ` for large view distances.
+    pub fn new_with_depth_mode(event_loop: &EventsLoop, depth_mode: DepthMode) -> Renderer {
         let instance = Instance::new(None, &crate::vulkano_win::required_extensions(), None).expect("failed to create instance");
         let surface = WindowBuilder::new().with_dimensions(LogicalSize { width: 1366.0, height: 768.0 }).build_vk_surface(event_loop, instance.clone()).unwrap();
         let physical = PhysicalDevice::enumerate(&instance).next().expect("no device available");
@@ -287,22 +685,35 @@ impl Renderer {
 
         let chunk_lines_vg = Arc::new(VertexGroup::new(Vec::<VertexPositionColorAlpha>::new().iter().cloned(), Vec::new().iter().cloned(), 0, device.clone()));
         let occlusion_vg = Arc::new(VertexGroup::new(Vec::<VertexPositionObjectId>::new().iter().cloned(), Vec::new().iter().cloned(), 0, device.clone()));
-        let occlusion_cpu_buffer = CpuAccessibleBufferXalloc::<[u32]>::from_iter(device.clone(), BufferUsage::all(), vec![0u32; 320*240].iter().cloned()).expect("failed to create buffer");
 
-        let luma_avg_buffer = CpuAccessibleBufferXalloc::from_iter(device.clone(), BufferUsage::transfer_destination(), [0u16; 4].iter().cloned()).unwrap();
         let histogram_compute = Arc::new(Mutex::new(HistogramCompute::new(device.clone())));
+        let occlusion_readback = Arc::new(Mutex::new(OcclusionReadback::new(device.clone(), OCCLUSION_FRAME_SIZE)));
+        let hiz_compute = Arc::new(Mutex::new(HiZCompute::new(device.clone(), OCCLUSION_FRAME_SIZE, depth_mode)));
 
         let mut info = RenderInfo {
             device,
             image_num: 0,
+            frame_index: 0,
             dimensions: [1024, 768],
             camera_transform: Transform::identity(),
             view_mat: Matrix4::identity(),
             proj_mat: Matrix4::identity(),
-            fov: Deg(45f32),
+            unjittered_proj_mat: Matrix4::identity(),
+            prev_view_mat: Matrix4::identity(),
+            prev_proj_mat: Matrix4::identity(),
+            jitter: [0.0, 0.0],
+            camera: Camera::new(),
+            depth_mode,
             tonemapping_info: TonemappingInfo::default(),
-            luma_avg_buffer,
+            color_grading_info: ColorGradingInfo::default(),
+            taa_info: TemporalAAInfo::default(),
+            ao_info: AmbientOcclusionInfo::default(),
+            lines_info: LinesInfo::default(),
+            occlusion_info: OcclusionInfo::default(),
+            occlusion_culling_info: OcclusionCullingInfo::default(),
             histogram_compute,
+            occlusion_readback,
+            hiz_compute,
             tex_registry: tex_registry.clone(),
             queue_main,
             queue_offscreen,
@@ -316,29 +727,53 @@ impl Renderer {
                 text: Vec::new(),
                 occluders: OcclusionRenderQueue {
                     vertex_group: occlusion_vg,
-                    output_cpu_buffer: occlusion_cpu_buffer
                 },
-                meshes: Vec::new()
+                meshes: Vec::new(),
+                debug: DebugDraw::new(),
             })),
+            graph_attachments: HashMap::new(),
             debug_visualize_setting: DEBUG_VISUALIZE_DISABLED,
+            view_name: None,
         };
 
-        let mut pipelines = Vec::<Box<dyn RenderPipelineAbstract>>::with_capacity(3);
-        pipelines.insert(GestaltRenderPass::Occlusion as usize,        Box::new(OcclusionRenderPipeline::new(&mut info, OCCLUSION_FRAME_SIZE)));
-        pipelines.insert(GestaltRenderPass::DeferredShading as usize,  Box::new(DeferredShadingRenderPipeline::new(&info)));
-        pipelines.insert(GestaltRenderPass::DeferredLighting as usize, Box::new(DeferredLightingRenderPipeline::new(&info)));
-        pipelines.insert(GestaltRenderPass::PostProcess as usize,      Box::new(PostProcessRenderPipeline::new(&info)));
-        pipelines.insert(GestaltRenderPass::Lines as usize,            Box::new(LinesRenderPipeline::new(&info)));
-        pipelines.insert(GestaltRenderPass::Text as usize,             Box::new(TextRenderPipeline::new(&info)));
+        let gbuffer = ["position", "normal", "albedo", "roughness", "metallic"];
+        let mut render_graph = RenderGraph::new();
+        render_graph.add_pass(PassDesc::new(graph::OCCLUSION_PASS).writes(&["occlusion"]),
+                       Box::new(OcclusionRenderPipeline::new(&mut info, OCCLUSION_FRAME_SIZE))).unwrap();
+        render_graph.add_external_pass(PassDesc::new(graph::VIEWS_PASS).reads(&["occlusion"]).writes(&[VIEW_TARGETS])).unwrap();
+        render_graph.add_external_pass(PassDesc::new(graph::PICKING_PASS)).unwrap();
+        render_graph.add_external_pass(PassDesc::new(graph::VIEWPORT_COMPOSITE_PASS).reads(&[VIEW_TARGETS]).writes(&[SWAPCHAIN])).unwrap();
+        render_graph.add_pass(PassDesc::new(graph::DEFERRED_SHADING_PASS).reads(&[VIEW_TARGETS]).writes(&gbuffer).writes(&["velocity", "main_depth"]),
+                       Box::new(DeferredShadingRenderPipeline::new(&info))).unwrap();
+        render_graph.add_pass(PassDesc::new(graph::DEFERRED_LIGHTING_PASS).reads(&gbuffer).writes(&["ao", "ao_scratch", "hdr_diffuse", "hdr_specular"]),
+                       Box::new(DeferredLightingRenderPipeline::new(&info))).unwrap();
+        render_graph.add_pass(PassDesc::new(graph::TEMPORAL_AA_PASS).reads(&["hdr_diffuse", "hdr_specular", "velocity"]).writes(&["taa_history"]),
+                       Box::new(TemporalAARenderPipeline::new(&info))).unwrap();
+        render_graph.add_pass(PassDesc::new(graph::POST_PROCESS_PASS).reads(&gbuffer).reads(&["hdr_diffuse", "hdr_specular", "occlusion", "taa_history", "velocity", "ao"])
+                           .writes(&[SWAPCHAIN, "scene_color", "luma_render"]),
+                       Box::new(PostProcessRenderPipeline::new(&info))).unwrap();
+        render_graph.add_pass(PassDesc::new(graph::LINES_PASS).reads(&["main_depth"]).writes(&[SWAPCHAIN]),
+                       Box::new(LinesRenderPipeline::new(&info))).unwrap();
+        render_graph.add_external_pass(PassDesc::new(graph::TEXT_PASS).reads(&["main_depth"]).writes(&[SWAPCHAIN])).unwrap();
+        // text, picking and imgui pipelines are stored separately
+        let text_pipeline = TextRenderPipeline::new(&info);
+        let picking_pipeline = PickingRenderPipeline::new(&info);
+
+        let targets = images.iter().map(|i| i.clone() as Arc<dyn ImageViewAccess + Send + Sync>).collect();
 
         Renderer {
             surface,
             swapchain,
             images,
+            targets,
             recreate_swapchain: false,
-            pipelines,
+            graph: render_graph,
             info,
+            text_pipeline,
+            picking_pipeline,
             imgui_pipeline: None,
+            views: Vec::new(),
+            exposure_initialized: false,
         }
     }
 
@@ -353,8 +788,90 @@ impl Renderer {
         self
     }
 
+    /// Gets the text pipeline, e.g. to register fonts with
+    /// [register_font](../pipeline/text/struct.TextRenderPipeline.html#method.register_font).
+    pub fn text_pipeline_mut(&mut self) -> &mut TextRenderPipeline {
+        &mut self.text_pipeline
+    }
+
+    /// Gets the render graph, e.g. to add custom passes. See the [graph module](../graph/index.html).
+    pub fn graph_mut(&mut self) -> &mut RenderGraph {
+        &mut self.graph
+    }
+
+    /// Adds a custom pass to the main view, after any other custom passes at the same insertion point.
+    ///
+    /// The pipeline gets the main view's `RenderInfo`, with the built-in attachments in `info.attachments`
+    /// and the ones the graph allocates for `desc.creates` in `info.graph_attachments`. The attachments the
+    /// insertion point implies are added to `desc`'s declarations. Custom passes don't run in offscreen views,
+    /// and only `Overlay` passes run during split-screen.
+    ///
+    /// ```ignore
+    /// let desc = PassDesc::new("hud").reads(&["hud_atlas"]);
+    /// renderer.add_custom_pass(InsertionPoint::Overlay, desc, Box::new(HudPipeline::new(&renderer.info)))?;
+    /// ```
+    pub fn add_custom_pass(&mut self, point: InsertionPoint, desc: PassDesc,
+                           pipeline: Box<dyn RenderPipelineAbstract>) -> Result<(), RenderGraphError> {
+        self.graph.add_pass_at(point, desc, pipeline)
+    }
+
+    /// Removes a pass added with `add_custom_pass`, returning its pipeline.
+    pub fn remove_custom_pass(&mut self, name: &str) -> Result<Box<dyn RenderPipelineAbstract>, RenderGraphError> {
+        self.graph.remove_pass_at(name)
+    }
+
+    /// IDs of the objects visible in the last occlusion frame that has been read back.
+    /// Lags a few frames behind, see `occlusion_results` for which frame it is from.
+    pub fn visible_object_ids(&self) -> HashSet<u32> {
+        self.info.occlusion_readback.lock().results.visible_ids()
+    }
+
+    /// Visibility and per-object pixel coverage from the last occlusion frame that has been read back.
+    pub fn occlusion_results(&self) -> OcclusionResults {
+        self.info.occlusion_readback.lock().results.clone()
+    }
+
+    /// Requests a pick at a pixel of the window, and returns what's under it once the result has been read
+    /// back a few frames later. See [PickingRenderPipeline::pick](../pipeline/picking/struct.PickingRenderPipeline.html#method.pick).
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<PickResult> {
+        self.picking_pipeline.pick(x, y)
+    }
+
+    /// Adds an offscreen view drawing into a `dimensions` sized texture, registered in the texture registry as
+    /// `name`. Replaces any existing view with the same name. See the [view module](../view/index.html).
+    pub fn add_view(&mut self, name: &str, dimensions: [u32; 2]) -> &mut RenderView {
+        // dropping the old view unregisters its target, so it has to go before the new one is registered
+        self.remove_view(name);
+        let view = RenderView::new(name, dimensions, &self.info);
+        self.views.push(view);
+        self.views.last_mut().unwrap()
+    }
+
+    /// Removes an offscreen view and unregisters its texture. Returns false if there is no view with that name.
+    pub fn remove_view(&mut self, name: &str) -> bool {
+        let count = self.views.len();
+        self.views.retain(|v| v.name() != name);
+        self.views.len() != count
+    }
+
+    /// Adds a split-screen view, drawn into a region of the window. Replaces any existing view with the same name.
+    /// While any enabled view has a viewport, the main view isn't drawn, and only picking and occlusion culling
+    /// still use the camera passed to `draw`. World-space text isn't drawn in split-screen, only screen text.
+    /// See the [view module](../view/index.html).
+    pub fn add_viewport(&mut self, name: &str, rect: ViewportRect) -> &mut RenderView {
+        let (_, size) = rect.pixels(self.info.dimensions);
+        let view = self.add_view(name, [size[0].max(1), size[1].max(1)]);
+        view.viewport = Some(rect);
+        view
+    }
+
+    /// Gets an offscreen or split-screen view by name, e.g. to move its camera.
+    pub fn view_mut(&mut self, name: &str) -> Option<&mut RenderView> {
+        self.views.iter_mut().find(|v| v.name() == name)
+    }
+
     /// Draw all objects in the render queue. Called every frame in the game loop.
-    pub fn draw(&mut self, camera: &Camera, _dt: f32, transform: Transform) -> Result<SwapchainAcquireFuture<Window>, RendererDrawError> {
+    pub fn draw(&mut self, camera: &Camera, dt: f32, transform: Transform) -> Result<SwapchainAcquireFuture<Window>, RendererDrawError> {
         self.info.dimensions = match self.surface.window().get_inner_size() {
             Some(logical_size) => [logical_size.width as u32, logical_size.height as u32],
             None => [800, 600]
@@ -365,8 +882,8 @@ impl Renderer {
             return Err(RendererDrawError::WindowMinimized);
         }
 
-        self.info.view_mat = Matrix4::from(transform.rotation) * Matrix4::from_translation((transform.position * -1.0).to_vec());
-        self.info.proj_mat = VULKAN_CORRECT_CLIP * cgmath::perspective(camera.fov, { self.info.dimensions[0] as f32 / self.info.dimensions[1] as f32 }, 0.1, 100.0);
+        self.info.frame_index += 1;
+        self.info.update_view(camera, &transform);
 
         if self.recreate_swapchain {
             info!(Renderer, "Recreating swapchain");
@@ -381,13 +898,14 @@ impl Renderer {
 
             std::mem::replace(&mut self.swapchain, new_swapchain);
             std::mem::replace(&mut self.images, new_images);
+            self.targets = self.images.iter().map(|i| i.clone() as Arc<dyn ImageViewAccess + Send + Sync>).collect();
 
             self.info.attachments = recreate_attachments(self.info.device.clone(), self.info.dimensions,
                                                          Some(self.info.attachments.occlusion.as_ref().unwrap().clone()));
 
-            for p in self.pipelines.iter_mut() {
-                p.remove_framebuffers();
-            }
+            self.graph.remove_framebuffers();
+            self.text_pipeline.remove_framebuffers();
+            self.picking_pipeline.remove_framebuffers();
             if let Some(p) = &mut self.imgui_pipeline {
                 p.remove_framebuffers();
             }
@@ -395,18 +913,21 @@ impl Renderer {
             self.recreate_swapchain = false;
         }
 
-        if !crate::compute::HISTOGRAM_COMPUTE_WORKING.load(Ordering::Relaxed) {
-            self.info.histogram_compute.lock().submit(self.info.device.clone(), self.info.queue_compute.clone());
-        }
-        else {
-            println!("histogram compute busy, skipping this frame");
+        let occlusion_resolution = self.info.occlusion_info.resolution;
+        if self.info.attachments.occlusion.as_ref().unwrap().dimensions() != occlusion_resolution
+                && occlusion_resolution[0] > 0 && occlusion_resolution[1] > 0 {
+            self.recreate_occlusion(occlusion_resolution);
         }
 
-        for p in self.pipelines.iter_mut() {
-            p.recreate_framebuffers_if_none(&self.images, &self.info);
+        if self.graph.allocate(self.info.device.clone(), self.info.dimensions) {
+            self.info.graph_attachments = self.graph.attachments().clone();
+            self.graph.remove_framebuffers();
         }
+        self.graph.recreate_framebuffers_if_none(&self.targets, &self.info);
+        self.text_pipeline.recreate_framebuffers_if_none(&self.targets, &self.info);
+        self.picking_pipeline.recreate_framebuffers_if_none(&self.targets, &self.info);
         if let Some(p) = &mut self.imgui_pipeline {
-            p.recreate_framebuffers_if_none(&self.images, &self.info);
+            p.recreate_framebuffers_if_none(&self.targets, &self.info);
         }
 
         let (image_num, future) = match vulkano::swapchain::acquire_next_image(self.swapchain.clone(), None) {
@@ -420,42 +941,57 @@ impl Renderer {
         };
         self.info.image_num = image_num;
 
-        self.info.fov = camera.fov.clone();
-        self.info.camera_transform = transform.clone();
-        let tonemap_info = self.info.tonemapping_info.clone();
-
-        let low_bin;
-        let high_bin;
-        {
-            let hist_lock = self.info.histogram_compute.lock();
-            low_bin = hist_lock.low_percentile_bin;
-            high_bin = hist_lock.high_percentile_bin;
+        update_exposure(&mut self.info, &mut self.exposure_initialized, dt);
+        for view in self.views.iter_mut().filter(|v| v.enabled) {
+            if let Some(rect) = view.viewport {
+                let (_, size) = rect.pixels(self.info.dimensions);
+                let size = [size[0].max(1), size[1].max(1)];
+                if size != view.info.dimensions {
+                    view.resize(size);
+                }
+            }
+            view.prepare(&self.info, dt);
         }
-
-        let bin_avg = (low_bin + high_bin) / 2.0;
-        let avg_log_luma = bin_avg / 4.6 - 10.0;
-        let avg_luma = 2f32.powf(avg_log_luma);
-        let ev100 = (avg_luma * 100.0 / 12.5).log2() + tonemap_info.exposure_adjustment;
-        let max_luma = 1.2 * 2f32.powf(ev100);
-        let exposure = 1.0 / max_luma;
-        //let exposure = exposure.max(tonemap_info.min_exposure);
-
-        self.info.tonemapping_info = TonemappingInfo {
-            adjust_speed: 0.5,
-            hist_low_percentile_bin: low_bin,
-            hist_high_percentile_bin: high_bin,
-            avg_scene_luma: avg_luma,
-            scene_ev100: ev100,
-            exposure,
-            exposure_adjustment: tonemap_info.exposure_adjustment,
-            min_exposure: tonemap_info.min_exposure,
-            max_exposure: tonemap_info.max_exposure,
-            vignette_opacity: tonemap_info.vignette_opacity
-        };
+        self.info.occlusion_readback.lock().poll();
+        self.info.hiz_compute.lock().poll();
+        self.picking_pipeline.poll();
+        self.info.render_queues.write().unwrap().debug.advance(dt);
 
         Ok(future)
     }
 
+    /// Recreates the occlusion pass, its readback and Hi-Z pyramid at a new resolution.
+    fn recreate_occlusion(&mut self, resolution: [u32; 2]) {
+        info!(Renderer, "Recreating occlusion pass at {}x{}", resolution[0], resolution[1]);
+        let pipeline = OcclusionRenderPipeline::new(&mut self.info, resolution);
+        self.graph.replace_pipeline(graph::OCCLUSION_PASS, Box::new(pipeline)).unwrap();
+        *self.info.occlusion_readback.lock() = OcclusionReadback::new(self.info.device.clone(), resolution);
+        *self.info.hiz_compute.lock() = HiZCompute::new(self.info.device.clone(), resolution, self.info.depth_mode);
+    }
+
+    /// Clears the swapchain image and blits the split-screen views into their regions.
+    fn build_viewport_composite(&self) -> AutoCommandBuffer {
+        let image = self.images[self.info.image_num].clone();
+        let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(self.info.device.clone(), self.info.queue_main.family()).unwrap()
+            .clear_color_image(image.clone(), ClearValue::Float([0.0, 0.0, 0.0, 1.0])).unwrap();
+        for view in self.views.iter().filter(|v| v.enabled) {
+            let rect = match view.viewport {
+                Some(rect) => rect,
+                None => continue
+            };
+            let (origin, size) = rect.pixels(self.info.dimensions);
+            if size[0] == 0 || size[1] == 0 {
+                continue;
+            }
+            let source = view.info.dimensions;
+            cb = cb.blit_image(view.target(), [0, 0, 0], [source[0] as i32, source[1] as i32, 1], 0, 0,
+                               image.clone(), [origin[0] as i32, origin[1] as i32, 0],
+                               [(origin[0] + size[0]) as i32, (origin[1] + size[1]) as i32, 1], 0, 0,
+                               1, Filter::Linear).unwrap();
+        }
+        cb.build().unwrap()
+    }
+
     pub fn draw_imgui(&mut self, ui: imgui::Ui) {
         match ui.mouse_cursor() {
             Some(mouse_cursor) => {
@@ -478,29 +1014,41 @@ impl Renderer {
     }
 
     pub fn submit(&mut self, image_acq_fut: SwapchainAcquireFuture<Window>) {
-        let mut main_future:      Box<dyn GpuFuture> = Box::new(image_acq_fut);
-        let occlusion_finished_future;
-
-        let (cb, q) = self.pipelines[GestaltRenderPass::Occlusion as usize].build_command_buffer(&self.info);
-        occlusion_finished_future = vulkano::sync::now(self.info.device.clone())
-            .then_execute(q.clone(), cb).unwrap()
-            .then_signal_semaphore_and_flush().unwrap();
-
-        let (cb, q) = self.pipelines[GestaltRenderPass::DeferredShading as usize].build_command_buffer(&self.info);
-        main_future = Box::new(main_future.then_execute(q.clone(), cb).unwrap());
+        let mut external = HashMap::new();
+
+        // offscreen views go first, so materials sample this frame's view targets
+        let mut view_cbs = Vec::new();
+        for view in self.views.iter_mut().filter(|v| v.enabled) {
+            let (pipelines, info) = view.pipelines_mut();
+            for p in pipelines.iter_mut() {
+                view_cbs.push(p.build_command_buffer(info));
+            }
+        }
+        external.insert(graph::VIEWS_PASS, view_cbs);
 
-        let (cb, q) = self.pipelines[GestaltRenderPass::DeferredLighting as usize].build_command_buffer(&self.info);
-        main_future = Box::new(main_future.then_execute(q.clone(), cb).unwrap());
+        if self.picking_pipeline.has_request() {
+            external.insert(graph::PICKING_PASS, vec![self.picking_pipeline.build_command_buffer(&self.info)]);
+        }
 
-        let (cb, q) = self.pipelines[GestaltRenderPass::PostProcess as usize].build_command_buffer(&self.info);
-        main_future = Box::new(main_future.join(occlusion_finished_future)
-            .then_execute(q.clone(), cb).unwrap());
+        // split-screen, the viewports replace the main view
+        let split_screen = self.views.iter().any(|v| v.enabled && v.viewport.is_some());
+        if split_screen {
+            external.insert(graph::VIEWPORT_COMPOSITE_PASS, vec![(self.build_viewport_composite(), self.info.queue_main.clone())]);
+        }
+        let mut skipped: Vec<String> = Vec::new();
+        if split_screen {
+            skipped.extend([graph::DEFERRED_SHADING_PASS, graph::DEFERRED_LIGHTING_PASS, graph::TEMPORAL_AA_PASS,
+                            graph::POST_PROCESS_PASS, graph::LINES_PASS].iter().map(|name| name.to_string()));
+            for point in [InsertionPoint::BeforeLighting, InsertionPoint::AfterPostProcess].iter() {
+                skipped.extend(self.graph.passes_at(*point).map(|name| name.to_string()));
+            }
+        }
+        let skipped: Vec<&str> = skipped.iter().map(|name| name.as_str()).collect();
 
-        let (cb, q) = self.pipelines[GestaltRenderPass::Lines as usize].build_command_buffer(&self.info);
-        main_future = Box::new(main_future.then_execute(q.clone(), cb).unwrap());
+        self.text_pipeline.screen_only = split_screen;
+        external.insert(graph::TEXT_PASS, vec![self.text_pipeline.build_command_buffer(&self.info)]);
 
-        let (cb, q) = self.pipelines[GestaltRenderPass::Text as usize].build_command_buffer(&self.info);
-        main_future = Box::new(main_future.then_execute(q.clone(), cb).unwrap());
+        let mut main_future = self.graph.execute(&self.info, Box::new(image_acq_fut), external, &skipped);
 
         if self.imgui_pipeline.is_some() {
             if let Some(cbs) = self.imgui_pipeline.as_mut().unwrap().cached_command_buffers.take() {
@@ -517,6 +1065,7 @@ impl Renderer {
         match final_main_future {
             Ok(mut f) => {
                 // This wait is required when using NVIDIA or running on macOS. See https://github.com/vulkano-rs/vulkano/issues/1247
+                // It also means GPU readbacks (histogram, occlusion, Hi-Z, picking) are always ready on the next frame.
                 f.wait(None).unwrap();
                 f.cleanup_finished();
             }
@@ -530,3 +1079,58 @@ impl Renderer {
         }
     }
 }
+
+
+/// Meters the latest histogram of `info` and adapts its exposure towards it.
+pub(crate) fn update_exposure(info: &mut RenderInfo, exposure_initialized: &mut bool, dt: f32) {
+    let metered = {
+        let mut hist_lock = info.histogram_compute.lock();
+        hist_lock.poll();
+        if hist_lock.new_result {
+            hist_lock.new_result = false;
+            Some((hist_lock.low_percentile_bin, hist_lock.high_percentile_bin))
+        }
+        else {
+            // histogram was skipped or empty this frame, keep adapting towards the last target
+            None
+        }
+    };
+
+    let tonemap_info = &mut info.tonemapping_info;
+    if let Some((low_bin, high_bin)) = metered {
+        let bin_avg = (low_bin + high_bin) / 2.0;
+        let avg_log_luma = bin_avg / 4.6 - 10.0;
+        let avg_luma = 2f32.powf(avg_log_luma);
+        tonemap_info.hist_low_percentile_bin = low_bin;
+        tonemap_info.hist_high_percentile_bin = high_bin;
+        tonemap_info.avg_scene_luma = avg_luma;
+        tonemap_info.target_ev100 = (avg_luma * 100.0 / 12.5).log2()
+            .max(tonemap_info.min_ev100)
+            .min(tonemap_info.max_ev100);
+
+        if !*exposure_initialized {
+            // nothing to adapt from, snap straight to the first metered value
+            tonemap_info.scene_ev100 = tonemap_info.target_ev100;
+            *exposure_initialized = true;
+        }
+    }
+    if !*exposure_initialized || !tonemap_info.auto_exposure {
+        return;
+    }
+
+    let speed = if tonemap_info.target_ev100 < tonemap_info.scene_ev100 {
+        tonemap_info.adapt_speed_brighten
+    }
+    else {
+        tonemap_info.adapt_speed_darken
+    };
+    // frame-rate independent exponential approach
+    let t = 1.0 - (-dt.max(0.0) * speed).exp();
+    tonemap_info.scene_ev100 += (tonemap_info.target_ev100 - tonemap_info.scene_ev100) * t;
+
+    let ev100 = tonemap_info.scene_ev100
+        + tonemap_info.exposure_adjustment
+        + tonemap_info.compensation(tonemap_info.scene_ev100);
+    let max_luma = 1.2 * 2f32.powf(ev100);
+    tonemap_info.exposure = 1.0 / max_luma;
+}
@@ -9,7 +9,8 @@ const NORMAL_BUFFER:    usize = 1;
 const ALBEDO_BUFFER:    usize = 2;
 const ROUGHNESS_BUFFER: usize = 3;
 const METALLIC_BUFFER:  usize = 4;
-const DEPTH_BUFFER:     usize = 5;
+const VELOCITY_BUFFER:  usize = 5;
+const DEPTH_BUFFER:     usize = 6;
 
 const FLOAT_ATTACHMENT_DESC: AttachmentDescription = AttachmentDescription {
     format: Format::R16G16B16A16Sfloat,
@@ -22,8 +23,19 @@ const FLOAT_ATTACHMENT_DESC: AttachmentDescription = AttachmentDescription {
     final_layout: ImageLayout::ColorAttachmentOptimal
 };
 
+const VELOCITY_ATTACHMENT_DESC: AttachmentDescription = AttachmentDescription {
+    format: Format::R16G16Sfloat,
+    samples: 1,
+    load: LoadOp::Clear,
+    store: StoreOp::Store,
+    stencil_load: LoadOp::DontCare,
+    stencil_store: StoreOp::DontCare,
+    initial_layout: ImageLayout::Undefined,
+    final_layout: ImageLayout::ColorAttachmentOptimal
+};
+
 unsafe impl RenderPassDesc for DeferredShadingRenderPass {
-    fn num_attachments(&self) -> usize { 6 }
+    fn num_attachments(&self) -> usize { 7 }
     fn attachment_desc(&self, num: usize) -> Option<AttachmentDescription> {
         match num {
             POSITION_BUFFER => Some(FLOAT_ATTACHMENT_DESC),
@@ -31,6 +43,7 @@ unsafe impl RenderPassDesc for DeferredShadingRenderPass {
             ALBEDO_BUFFER => Some(FLOAT_ATTACHMENT_DESC),
             ROUGHNESS_BUFFER => Some(FLOAT_ATTACHMENT_DESC),
             METALLIC_BUFFER => Some(FLOAT_ATTACHMENT_DESC),
+            VELOCITY_BUFFER => Some(VELOCITY_ATTACHMENT_DESC),
             DEPTH_BUFFER => Some(AttachmentDescription {
                 format: Format::D32Sfloat,
                 samples: 1,
@@ -64,6 +77,7 @@ unsafe impl RenderPassDesc for DeferredShadingRenderPass {
                     (ALBEDO_BUFFER, ImageLayout::ColorAttachmentOptimal),
                     (ROUGHNESS_BUFFER, ImageLayout::ColorAttachmentOptimal),
                     (METALLIC_BUFFER, ImageLayout::ColorAttachmentOptimal),
+                    (VELOCITY_BUFFER, ImageLayout::ColorAttachmentOptimal),
                 ],
                 depth_stencil: Some((DEPTH_BUFFER, ImageLayout::DepthStencilAttachmentOptimal)),
                 input_attachments: vec![],
@@ -12,5 +12,11 @@ pub use self::lines::LinesRenderPass;
 pub mod occlusion;
 pub use self::occlusion::OcclusionRenderPass;
 
+pub mod picking;
+pub use self::picking::PickingRenderPass;
+
 pub mod postprocess;
 pub use self::postprocess::PostProcessRenderPass;
+
+pub mod temporal_aa;
+pub use self::temporal_aa::TemporalAARenderPass;
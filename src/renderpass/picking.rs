@@ -0,0 +1,72 @@
+use vulkano::framebuffer::{RenderPassDesc, AttachmentDescription, PassDescription, PassDependencyDescription, LoadOp, StoreOp, RenderPassDescClearValues};
+use vulkano::image::ImageLayout;
+use vulkano::format::{Format, ClearValue};
+
+/// Render pass for the picking phase.
+pub struct PickingRenderPass { }
+
+const OBJECT_ID_BUFFER:   usize = 0;
+const TRIANGLE_ID_BUFFER: usize = 1;
+const POSITION_BUFFER:    usize = 2;
+const DEPTH_BUFFER:       usize = 3;
+
+unsafe impl RenderPassDesc for PickingRenderPass {
+    fn num_attachments(&self) -> usize { 4 }
+    fn attachment_desc(&self, num: usize) -> Option<AttachmentDescription> {
+        let color = |format| Some(AttachmentDescription {
+            format,
+            samples: 1,
+            load: LoadOp::Clear,
+            store: StoreOp::Store,
+            stencil_load: LoadOp::DontCare,
+            stencil_store: StoreOp::DontCare,
+            initial_layout: ImageLayout::Undefined,
+            final_layout: ImageLayout::ColorAttachmentOptimal
+        });
+        match num {
+            OBJECT_ID_BUFFER => color(Format::R32Uint),
+            TRIANGLE_ID_BUFFER => color(Format::R32Uint),
+            POSITION_BUFFER => color(Format::R32G32B32A32Sfloat),
+            DEPTH_BUFFER => Some(AttachmentDescription {
+                format: Format::D32Sfloat,
+                samples: 1,
+                load: LoadOp::Clear,
+                store: StoreOp::DontCare,
+                stencil_load: LoadOp::DontCare,
+                stencil_store: StoreOp::DontCare,
+                initial_layout: ImageLayout::Undefined,
+                final_layout: ImageLayout::DepthStencilAttachmentOptimal
+            }),
+            _ => None
+        }
+    }
+
+    fn num_subpasses(&self) -> usize { 1 }
+    fn subpass_desc(&self, num: usize) -> Option<PassDescription> {
+        match num {
+            0 => Some(PassDescription {
+                color_attachments: vec![
+                    (OBJECT_ID_BUFFER, ImageLayout::ColorAttachmentOptimal),
+                    (TRIANGLE_ID_BUFFER, ImageLayout::ColorAttachmentOptimal),
+                    (POSITION_BUFFER, ImageLayout::ColorAttachmentOptimal),
+                ],
+                depth_stencil: Some((DEPTH_BUFFER, ImageLayout::DepthStencilAttachmentOptimal)),
+                input_attachments: vec![],
+                resolve_attachments: vec![],
+                preserve_attachments: vec![]
+            }),
+            _ => None
+        }
+    }
+
+    fn num_dependencies(&self) -> usize { 0 }
+    fn dependency_desc(&self, _num: usize) -> Option<PassDependencyDescription> { None }
+}
+
+
+unsafe impl RenderPassDescClearValues<Vec<ClearValue>> for PickingRenderPass {
+    fn convert_clear_values(&self, values: Vec<ClearValue>) -> Box<dyn Iterator<Item = ClearValue>> {
+        // FIXME: safety checks
+        Box::new(values.into_iter())
+    }
+}
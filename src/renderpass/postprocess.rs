@@ -63,7 +63,7 @@ unsafe impl RenderPassDesc for PostProcessRenderPass {
                 final_layout: ImageLayout::ColorAttachmentOptimal
             }),
             LUMA_BUFFER => Some(AttachmentDescription {
-                format: Format::R32Sint,
+                format: Format::R32Sfloat,
                 samples: 1,
                 load: LoadOp::Clear,
                 store: StoreOp::Store,
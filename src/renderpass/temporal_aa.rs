@@ -0,0 +1,52 @@
+use vulkano::framebuffer::{RenderPassDesc, AttachmentDescription, PassDescription, PassDependencyDescription, LoadOp, StoreOp, RenderPassDescClearValues};
+use vulkano::image::ImageLayout;
+use vulkano::format::{Format, ClearValue};
+
+/// Render pass for the temporal anti-aliasing history resolve.
+pub struct TemporalAARenderPass { }
+
+const RESOLVED_OUT: usize = 0;
+
+unsafe impl RenderPassDesc for TemporalAARenderPass {
+    fn num_attachments(&self) -> usize { 1 }
+    fn attachment_desc(&self, num: usize) -> Option<AttachmentDescription> {
+        match num {
+            RESOLVED_OUT => Some(AttachmentDescription {
+                format: Format::R16G16B16A16Sfloat,
+                samples: 1,
+                load: LoadOp::DontCare,
+                store: StoreOp::Store,
+                stencil_load: LoadOp::DontCare,
+                stencil_store: StoreOp::DontCare,
+                initial_layout: ImageLayout::Undefined,
+                final_layout: ImageLayout::ColorAttachmentOptimal
+            }),
+            _ => None
+        }
+    }
+
+    fn num_subpasses(&self) -> usize { 1 }
+    fn subpass_desc(&self, num: usize) -> Option<PassDescription> {
+        match num {
+            0 => Some(PassDescription {
+                color_attachments: vec![ (RESOLVED_OUT, ImageLayout::ColorAttachmentOptimal) ],
+                depth_stencil: None,
+                input_attachments: vec![],
+                resolve_attachments: vec![],
+                preserve_attachments: vec![]
+            }),
+            _ => None
+        }
+    }
+
+    fn num_dependencies(&self) -> usize { 0 }
+    fn dependency_desc(&self, _num: usize) -> Option<PassDependencyDescription> { None }
+}
+
+
+unsafe impl RenderPassDescClearValues<Vec<ClearValue>> for TemporalAARenderPass {
+    fn convert_clear_values(&self, values: Vec<ClearValue>) -> Box<dyn Iterator<Item = ClearValue>> {
+        // FIXME: safety checks
+        Box::new(values.into_iter())
+    }
+}
@@ -9,13 +9,15 @@ layout (input_attachment_index = 4, binding = 4) uniform subpassInput gbufferMet
 layout (set = 0, binding = 5) uniform sampler2D irrCubemap;
 layout (set = 0, binding = 6) uniform sampler2D radCubemap;
 layout (set = 0, binding = 7) uniform sampler2D brdfLookup;
+layout (set = 0, binding = 8) uniform sampler2D aoBuffer;
 
 layout(location = 0) out vec4 diffuse_out;
 layout(location = 1) out vec4 specular_out;
 
 layout(push_constant) uniform Constants {
     mat4 view;
-    vec3 view_pos;
+    // w = 1: camera position. w = 0: direction towards an orthographic camera.
+    vec4 view_pos;
     uint debug_vis_mode;
 } constants;
 
@@ -36,11 +38,12 @@ void main() {
 
     vec3 frag_pos = subpassLoad(gbufferPosition).rgb;
     vec3 N = normalize(subpassLoad(gbufferNormal).rgb);
-    vec3 V = normalize(constants.view_pos - frag_pos);
+    vec3 V = normalize(constants.view_pos.xyz - frag_pos * constants.view_pos.w);
     vec3 R = reflect(-V, N);
     vec3 albedo = subpassLoad(gbufferAlbedo).rgb;
     float roughness = 0.9;//subpassLoad(gbufferRoughness).r;
     float metallic = subpassLoad(gbufferMetallic).r;
+    float ao = texelFetch(aoBuffer, ivec2(gl_FragCoord.xy), 0).r;
 
     // irradiance for point lights
     vec3 point_lights_diff = vec3(0.0);
@@ -65,7 +68,7 @@ void main() {
     // diffuse irradiance
     vec3 irradiance = texture(irrCubemap, uv).rgb;
     vec3 diffuse    = irradiance * albedo;
-    vec3 ibl_diffuse    = (kD * diffuse); // * ao;
+    vec3 ibl_diffuse    = kD * diffuse * ao;
 
     // equirectangular UVs from reflected normal
     uv = vec2(atan(R.z, R.x), acos(R.y));
@@ -75,7 +78,10 @@ void main() {
     const float MAX_REFLECTION_LOD = 4.0;
     vec3 prefilteredColor = textureLod(radCubemap, uv,  roughness * MAX_REFLECTION_LOD).rgb;
     vec2 envBRDF  = texture(brdfLookup, vec2(max(dot(N, V), 0.0), roughness)).rg;
-    vec3 ibl_specular = prefilteredColor * (F * envBRDF.x + envBRDF.y);
+    // specular occlusion approximation from Lagarde & de Rousiers, "Moving Frostbite to PBR"
+    float NdotV = max(dot(N, V), 0.0);
+    float specular_ao = saturate(pow(NdotV + ao, exp2(-16.0 * roughness - 1.0)) - 1.0 + ao);
+    vec3 ibl_specular = prefilteredColor * (F * envBRDF.x + envBRDF.y) * specular_ao;
 
     // absolute luminance to pipeline luminance
     diffuse_out = vec4(vec3((point_lights_diff + ibl_diffuse) / INTERNAL_HDR_DIV), 1.0);
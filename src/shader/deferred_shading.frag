@@ -4,12 +4,16 @@ layout(location = 0) in vec3 ws_normal;
 layout(location = 1) in vec3 tangent;
 layout(location = 2) in vec2 uv;
 layout(location = 3) in vec3 pos;
+layout(location = 4) in vec4 curr_clip_pos;
+layout(location = 5) in vec4 prev_clip_pos;
+layout(location = 6) in float view_depth;
 
 layout(location = 0) out vec4 gbuffer_position;
 layout(location = 1) out vec4 gbuffer_normal;
 layout(location = 2) out vec4 gbuffer_albedo;
 layout(location = 3) out vec4 gbuffer_roughness;
 layout(location = 4) out vec4 gbuffer_metallic;
+layout(location = 5) out vec2 gbuffer_velocity;
 
 layout(set = 0, binding = 0) uniform sampler2D tex_albedo;
 layout(set = 0, binding = 1) uniform sampler2D tex_normal;
@@ -23,18 +27,12 @@ layout(push_constant) uniform Constants {
 
 layout(set = 1, binding = 0) uniform InstanceData {
     mat4 world;
+    mat4 prev_world;
 } instancedata;
 
-const float NEAR_PLANE = 0.1f;
-const float FAR_PLANE = 1000.0f;
-
-float linearDepth(float depth) {
-    float z = depth * 2.0f - 1.0f;
-    return (2.0f * NEAR_PLANE * FAR_PLANE) / (FAR_PLANE + NEAR_PLANE - z * (FAR_PLANE - NEAR_PLANE));
-}
-
 void main() {
-    gbuffer_position = vec4(pos, linearDepth(gl_FragCoord.z));
+    // linear depth, independent of the projection
+    gbuffer_position = vec4(pos, view_depth);
 
     vec3 ts_normal = texture(tex_normal, uv).xyz;
     // flip green channel
@@ -45,4 +43,9 @@ void main() {
     gbuffer_albedo = texture(tex_albedo, uv);
     gbuffer_roughness = vec4(texture(tex_roughness, uv).x);
     gbuffer_metallic = vec4(texture(tex_metal, uv).x);
+
+    // NDC -> UV space, current - previous
+    vec2 curr_ndc = curr_clip_pos.xy / curr_clip_pos.w;
+    vec2 prev_ndc = prev_clip_pos.xy / prev_clip_pos.w;
+    gbuffer_velocity = (curr_ndc - prev_ndc) * 0.5;
 }
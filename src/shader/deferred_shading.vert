@@ -9,6 +9,9 @@ layout(location = 0) out vec3 normal_out;
 layout(location = 1) out vec3 tangent_out;
 layout(location = 2) out vec2 uv_out;
 layout(location = 3) out vec3 surface_pos_out;
+layout(location = 4) out vec4 curr_clip_pos_out;
+layout(location = 5) out vec4 prev_clip_pos_out;
+layout(location = 6) out float view_depth_out;
 
 layout(push_constant) uniform Constants {
     mat4 view;
@@ -17,14 +20,26 @@ layout(push_constant) uniform Constants {
 
 layout(set = 1, binding = 0) uniform InstanceData {
     mat4 world;
+    mat4 prev_world;
 } instance;
 
+layout(set = 2, binding = 0) uniform FrameData {
+    // unjittered, so motion vectors don't pick up TAA jitter
+    mat4 view_proj;
+    mat4 prev_view_proj;
+} frame;
+
 
 void main() {
     normal_out = transpose(inverse(mat3(instance.world))) * normal;
     tangent_out = tangent;
     uv_out = uv;
     surface_pos_out = (instance.world * vec4(position, 1.0)).xyz;
+    // view space looks down -z
+    view_depth_out = -(constants.view * vec4(surface_pos_out, 1.0)).z;
+
+    curr_clip_pos_out = frame.view_proj * instance.world * vec4(position, 1.0);
+    prev_clip_pos_out = frame.prev_view_proj * instance.prev_world * vec4(position, 1.0);
 
     gl_Position = constants.proj * constants.view * instance.world * vec4(position, 1.0);
 }
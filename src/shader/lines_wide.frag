@@ -0,0 +1,32 @@
+#version 450
+
+layout(location = 0) in vec4 v_color;
+layout(location = 1) noperspective in float v_dist;
+layout(location = 2) in float v_half_width;
+layout(location = 3) noperspective in float v_along;
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform Constants {
+    vec2 screen_dimensions;
+    float default_width;
+    float feather;
+    float dash_length;
+    float gap_length;
+} constants;
+
+void main() {
+    // coverage falls off linearly over `feather` pixels at the edges
+    float feather = max(constants.feather, 0.0001);
+    float alpha = clamp((v_half_width - abs(v_dist)) / feather + 0.5, 0.0, 1.0);
+
+    if (constants.dash_length > 0.0) {
+        float along = mod(v_along, constants.dash_length + constants.gap_length);
+        alpha *= clamp((constants.dash_length - along) / feather + 0.5, 0.0, 1.0);
+    }
+
+    if (alpha <= 0.0) {
+        discard;
+    }
+    f_color = vec4(v_color.rgb, v_color.a * alpha);
+}
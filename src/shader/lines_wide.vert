@@ -0,0 +1,88 @@
+#version 450
+
+// Screen-space expanded lines. Each segment is two vertices in the storage buffer, and is drawn
+// as a quad of 6 vertices generated from gl_VertexIndex, so no vertex buffer is bound.
+
+struct LineVertex {
+    vec3 position;
+    // pixels, 0 = constants.default_width
+    float width;
+    vec4 color;
+};
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+} uniforms;
+
+layout(std430, set = 0, binding = 1) readonly buffer Segments {
+    LineVertex vertices[];
+} segments;
+
+layout(push_constant) uniform Constants {
+    vec2 screen_dimensions;
+    float default_width;
+    float feather;
+    float dash_length;
+    float gap_length;
+} constants;
+
+layout(location = 0) out vec4 v_color;
+// signed distance from the center of the line, in pixels
+layout(location = 1) noperspective out float v_dist;
+layout(location = 2) out float v_half_width;
+// distance along the segment, in pixels
+layout(location = 3) noperspective out float v_along;
+
+const float NEAR_W = 0.0001;
+
+void main() {
+    uint segment = gl_VertexIndex / 6;
+    uint corner = gl_VertexIndex % 6;
+    // two triangles: (start, -), (end, -), (end, +), (start, -), (end, +), (start, +)
+    const uint ends[6] = uint[](0, 1, 1, 0, 1, 0);
+    const float sides[6] = float[](-1.0, -1.0, 1.0, -1.0, 1.0, 1.0);
+
+    LineVertex a = segments.vertices[segment * 2];
+    LineVertex b = segments.vertices[segment * 2 + 1];
+    mat4 mvp = uniforms.proj * uniforms.view * uniforms.world;
+    vec4 clip_a = mvp * vec4(a.position, 1.0);
+    vec4 clip_b = mvp * vec4(b.position, 1.0);
+
+    // clip against the near plane, otherwise the projected direction flips
+    if (clip_a.w < NEAR_W && clip_b.w < NEAR_W) {
+        gl_Position = vec4(0.0, 0.0, 0.0, -1.0);
+        return;
+    }
+    if (clip_a.w < NEAR_W) {
+        clip_a = mix(clip_a, clip_b, (NEAR_W - clip_a.w) / (clip_b.w - clip_a.w));
+    }
+    else if (clip_b.w < NEAR_W) {
+        clip_b = mix(clip_b, clip_a, (NEAR_W - clip_b.w) / (clip_a.w - clip_b.w));
+    }
+
+    vec2 screen_a = clip_a.xy / clip_a.w * 0.5 * constants.screen_dimensions;
+    vec2 screen_b = clip_b.xy / clip_b.w * 0.5 * constants.screen_dimensions;
+    vec2 delta = screen_b - screen_a;
+    float len = length(delta);
+    vec2 dir = len > 0.0001 ? delta / len : vec2(1.0, 0.0);
+    vec2 normal = vec2(-dir.y, dir.x);
+
+    bool is_end = ends[corner] == 1;
+    float side = sides[corner];
+    LineVertex v = is_end ? b : a;
+    vec4 clip = is_end ? clip_b : clip_a;
+    float width = v.width > 0.0 ? v.width : constants.default_width;
+    // thin lines are drawn at 1px and faded instead, so they don't shimmer
+    float half_width = max(width, 1.0) * 0.5;
+    // pad by the feather so the falloff isn't cut off
+    float extent = half_width + constants.feather;
+    vec2 offset = normal * side * extent;
+
+    gl_Position = clip + vec4(offset / constants.screen_dimensions * 2.0 * clip.w, 0.0, 0.0);
+    v_color = vec4(v.color.rgb, v.color.a * min(width, 1.0));
+    v_dist = side * extent;
+    v_half_width = half_width;
+    v_along = is_end ? len : 0.0;
+}
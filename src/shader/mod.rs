@@ -14,6 +14,18 @@ pub mod lines {
             path: "src/shader/lines.frag"
         }
     }
+    pub mod wide_vertex {
+        vulkano_shaders::shader!{
+            ty: "vertex",
+            path: "src/shader/lines_wide.vert"
+        }
+    }
+    pub mod wide_fragment {
+        vulkano_shaders::shader!{
+            ty: "fragment",
+            path: "src/shader/lines_wide.frag"
+        }
+    }
 }
 
 /// Shader for rendering the skybox.
@@ -46,6 +58,18 @@ pub mod text {
             path: "src/shader/text.frag"
         }
     }
+    pub mod sdf_fragment {
+        vulkano_shaders::shader!{
+            ty: "fragment",
+            path: "src/shader/text_sdf.frag"
+        }
+    }
+    pub mod icon_fragment {
+        vulkano_shaders::shader!{
+            ty: "fragment",
+            path: "src/shader/text_icon.frag"
+        }
+    }
 }
 
 /// Deferred pipeline shading shaders
@@ -96,6 +120,22 @@ pub mod tonemapper {
     }
 }
 
+/// Temporal anti-aliasing resolve shaders
+pub mod temporal_aa {
+    pub mod vertex {
+        vulkano_shaders::shader!{
+            ty: "vertex",
+            path: "src/shader/taa.vert"
+        }
+    }
+    pub mod fragment {
+        vulkano_shaders::shader!{
+            ty: "fragment",
+            path: "src/shader/taa.frag"
+        }
+    }
+}
+
 /// Occlusion pass shaders
 pub mod occlusion {
     pub mod vertex {
@@ -112,10 +152,63 @@ pub mod occlusion {
     }
 }
 
+/// Picking pass shaders
+pub mod picking {
+    pub mod vertex {
+        vulkano_shaders::shader!{
+            ty: "vertex",
+            path: "src/shader/picking.vert"
+        }
+    }
+    pub mod fragment {
+        vulkano_shaders::shader!{
+            ty: "fragment",
+            path: "src/shader/picking.frag"
+        }
+    }
+}
 
+
+/// Luminance histogram shaders
 pub mod histogram {
-    vulkano_shaders::shader!{
-        ty: "compute",
-        path: "src/shader/histogram.comp"
+    pub mod bin {
+        vulkano_shaders::shader!{
+            ty: "compute",
+            path: "src/shader/histogram_bin.comp"
+        }
+    }
+    pub mod reduce {
+        vulkano_shaders::shader!{
+            ty: "compute",
+            path: "src/shader/histogram_reduce.comp"
+        }
+    }
+}
+
+
+/// Hierarchical-Z pyramid shaders
+pub mod hiz {
+    pub mod downsample {
+        vulkano_shaders::shader!{
+            ty: "compute",
+            path: "src/shader/hiz.comp"
+        }
     }
-}
\ No newline at end of file
+}
+
+
+/// Screen-space ambient occlusion shaders
+pub mod ssao {
+    pub mod main {
+        vulkano_shaders::shader!{
+            ty: "compute",
+            path: "src/shader/ssao.comp"
+        }
+    }
+    pub mod blur {
+        vulkano_shaders::shader!{
+            ty: "compute",
+            path: "src/shader/ssao_blur.comp"
+        }
+    }
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 world_position;
+layout(location = 1) in flat uint object_id;
+
+layout(location = 0) out uint object_id_out;
+layout(location = 1) out uint triangle_id_out;
+layout(location = 2) out vec4 position_out;
+
+void main() {
+    object_id_out = object_id;
+    triangle_id_out = uint(gl_PrimitiveID);
+    // w = 1 marks a hit, the cleared background has w = 0
+    position_out = vec4(world_position, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+
+layout(location = 0) out vec3 world_position;
+layout(location = 1) out flat uint object_id;
+
+layout(push_constant) uniform Constants {
+    mat4 world;
+    mat4 view_proj;
+    uint object_id;
+} constants;
+
+
+void main() {
+    vec4 world = constants.world * vec4(position, 1.0);
+    world_position = world.xyz;
+    object_id = constants.object_id;
+    gl_Position = constants.view_proj * world;
+}
@@ -8,6 +8,8 @@ layout(push_constant) uniform Constants {
 	mat4 matrix;
 	float sun_rotation;
 	float sun_transit;
+	// depth of the far plane, 1.0 or 0.0 with reverse-Z
+	float far_depth;
 } constants;
 
 #define LIGHT_DIR normalize( vec3( -1.0, -1.0, -1.0 ) )
@@ -9,9 +9,13 @@ layout(push_constant) uniform Constants {
 	mat4 matrix;
 	float sun_rotation;
 	float sun_transit;
+	// depth of the far plane, 1.0 or 0.0 with reverse-Z
+	float far_depth;
 } constants;
 
 void main() {
-	gl_Position = constants.matrix * vec4(position.xyz, 0.0);
+	// pin the sky to the far plane, so it's behind everything with any projection
+	vec4 clip = constants.matrix * vec4(position.xyz, 0.0);
+	gl_Position = vec4(clip.xy, clip.w * constants.far_depth, clip.w);
 	out_uv = vec2( uv.x, -abs(uv.y - 0.5) + 0.5 );
 }
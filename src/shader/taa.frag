@@ -0,0 +1,87 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D currentDiffuse;
+layout(set = 0, binding = 1) uniform sampler2D currentSpecular;
+layout(set = 0, binding = 2) uniform sampler2D velocityBuffer;
+layout(set = 0, binding = 3) uniform sampler2D historyBuffer;
+
+layout(location = 0) out vec4 resolved_out;
+
+layout(push_constant) uniform Constants {
+    vec2 screen_dimensions;
+    float history_weight;
+    uint history_valid;
+    uint enabled;
+} constants;
+
+#include "constants.inc"
+
+vec3 rgb_to_ycocg(vec3 c) {
+    return vec3( 0.25 * c.r + 0.5 * c.g + 0.25 * c.b,
+                 0.5  * c.r             - 0.5  * c.b,
+                -0.25 * c.r + 0.5 * c.g - 0.25 * c.b);
+}
+
+vec3 ycocg_to_rgb(vec3 c) {
+    return vec3(c.x + c.y - c.z,
+                c.x + c.z,
+                c.x - c.y - c.z);
+}
+
+vec3 load_current(ivec2 coord) {
+    coord = clamp(coord, ivec2(0), ivec2(constants.screen_dimensions) - 1);
+    return texelFetch(currentDiffuse, coord, 0).rgb + texelFetch(currentSpecular, coord, 0).rgb;
+}
+
+// clips the history color towards the center of the neighborhood AABB, rather than clamping per-channel
+vec3 clip_aabb(vec3 aabb_min, vec3 aabb_max, vec3 history) {
+    vec3 center = 0.5 * (aabb_max + aabb_min);
+    vec3 extents = 0.5 * (aabb_max - aabb_min) + 0.0001;
+    vec3 offset = history - center;
+    vec3 unit = abs(offset / extents);
+    float max_unit = max(unit.x, max(unit.y, unit.z));
+    if (max_unit > 1.0) {
+        return center + offset / max_unit;
+    }
+    return history;
+}
+
+void main() {
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    vec3 current = load_current(coord);
+
+    if (constants.enabled == 0 || constants.history_valid == 0) {
+        resolved_out = vec4(current, 1.0);
+        return;
+    }
+
+    // neighborhood bounds in YCoCg
+    vec3 neighborhood_min = vec3(1e20);
+    vec3 neighborhood_max = vec3(-1e20);
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            vec3 c = rgb_to_ycocg(load_current(coord + ivec2(x, y)));
+            neighborhood_min = min(neighborhood_min, c);
+            neighborhood_max = max(neighborhood_max, c);
+        }
+    }
+
+    vec2 uv = gl_FragCoord.xy / constants.screen_dimensions;
+    vec2 velocity = texelFetch(velocityBuffer, coord, 0).rg;
+    vec2 prev_uv = uv - velocity;
+    if (any(lessThan(prev_uv, vec2(0.0))) || any(greaterThan(prev_uv, vec2(1.0)))) {
+        // disoccluded from offscreen, no history to use
+        resolved_out = vec4(current, 1.0);
+        return;
+    }
+
+    vec3 history = texture(historyBuffer, prev_uv).rgb;
+    history = ycocg_to_rgb(clip_aabb(neighborhood_min, neighborhood_max, rgb_to_ycocg(history)));
+
+    // luminance weighting reduces flickering on bright sub-pixel features
+    float current_weight = (1.0 - constants.history_weight) / (1.0 + dot(current, LUMA_COMPONENTS));
+    float history_weight = constants.history_weight / (1.0 + dot(history, LUMA_COMPONENTS));
+    vec3 resolved = (current * current_weight + history * history_weight) / (current_weight + history_weight);
+
+    resolved_out = vec4(resolved, 1.0);
+}
@@ -7,8 +7,13 @@ layout(location = 2) in vec4 color;
 layout(location = 0) out vec2 v_tex_coords;
 layout(location = 1) out vec4 v_color;
 
+// text-local pixels to clip space
+layout(push_constant) uniform Constants {
+    mat4 transform;
+} constants;
+
 void main() {
-    gl_Position = vec4(position, 1.0);
+    gl_Position = constants.transform * vec4(position, 1.0);
     v_tex_coords = uv;
     v_color = color;
 }
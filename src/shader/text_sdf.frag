@@ -0,0 +1,63 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(set = 0, binding = 1) uniform Effects {
+    vec4 outline_color;
+    vec4 shadow_color;
+    vec4 glow_color;
+    // offset and widths in screen pixels
+    vec2 shadow_offset;
+    float shadow_softness;
+    float outline_width;
+    float glow_width;
+    // atlas texels per unit of encoded distance
+    float distance_range;
+} effects;
+
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 1) in vec4 v_color;
+layout(location = 0) out vec4 f_color;
+
+// coverage of the shape whose edge is at `edge`, anti-aliased over one screen pixel
+float coverage(float dist, float edge, float px_range) {
+    return clamp((dist - edge) * px_range + 0.5, 0.0, 1.0);
+}
+
+// non-premultiplied "over" operator
+vec4 over(vec4 top, vec4 bottom) {
+    float a = top.a + bottom.a * (1.0 - top.a);
+    vec3 c = (top.rgb * top.a + bottom.rgb * bottom.a * (1.0 - top.a)) / max(a, 1e-5);
+    return vec4(c, a);
+}
+
+void main() {
+    // screen pixels per unit of encoded distance, from the atlas texels this fragment covers. Varies with
+    // the size of each span, and across world-space text
+    vec2 texels_per_px = fwidth(v_tex_coords) * vec2(textureSize(tex, 0));
+    float px_range = effects.distance_range / max(0.5 * (texels_per_px.x + texels_per_px.y), 1e-5);
+    float outline_width = effects.outline_width / px_range;
+    float glow_width = effects.glow_width / px_range;
+    float shadow_softness = effects.shadow_softness / px_range;
+    vec2 shadow_offset = dFdx(v_tex_coords) * effects.shadow_offset.x + dFdy(v_tex_coords) * effects.shadow_offset.y;
+
+    float dist = texture(tex, v_tex_coords).r;
+    float outer_edge = 0.5 - outline_width;
+
+    vec4 result = vec4(0.0);
+    if (glow_width > 0.0) {
+        float glow = smoothstep(outer_edge - glow_width, outer_edge, dist);
+        result = vec4(effects.glow_color.rgb, effects.glow_color.a * glow);
+    }
+    if (effects.shadow_color.a > 0.0) {
+        float shadow_dist = texture(tex, v_tex_coords - shadow_offset).r;
+        float shadow = smoothstep(outer_edge - shadow_softness - 0.5 / px_range,
+                                  outer_edge + 0.5 / px_range, shadow_dist);
+        result = over(vec4(effects.shadow_color.rgb, effects.shadow_color.a * shadow), result);
+    }
+    if (outline_width > 0.0) {
+        result = over(vec4(effects.outline_color.rgb, effects.outline_color.a * coverage(dist, outer_edge, px_range)), result);
+    }
+    result = over(vec4(v_color.rgb, v_color.a * coverage(dist, 0.5, px_range)), result);
+
+    f_color = result;
+}
@@ -9,39 +9,80 @@ layout (input_attachment_index = 0, binding = 5) uniform subpassInput inputDiffu
 layout (input_attachment_index = 0, binding = 6) uniform subpassInput inputSpecular;
 
 layout(set = 0, binding = 7) uniform usampler2D occlusion_buffer;
+layout(set = 0, binding = 8) uniform sampler2D resolvedColor;
+layout(set = 0, binding = 9) uniform sampler2D velocityBuffer;
+layout(set = 0, binding = 10) uniform sampler2D aoBuffer;
+layout(set = 0, binding = 11) uniform sampler3D gradingLutA;
+layout(set = 0, binding = 12) uniform sampler3D gradingLutB;
 
 layout (location = 0) out vec4 swapchain_out;
 layout (location = 1) out vec4 scene_color;
-layout (location = 2) out uint luma_out;
+layout (location = 2) out float luma_out;
 
 layout(push_constant) uniform Constants {
     uint debug_vis_mode;
+    uint tonemap_operator;
     vec2 screen_dimensions;
-    float exposure_adjustment;
+    float exposure;
+    float white_point;
     float vignette_opacity;
+    uint color_grading_enabled;
+    float lut_blend;
 } constants;
 
 #include "constants.inc"
 #include "debug_vis.inc"
+#include "util.inc"
+#include "tonemapping.inc"
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 lo = color * 12.92;
+    vec3 hi = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(hi, lo, lessThanEqual(color, vec3(0.0031308)));
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    vec3 lo = color / 12.92;
+    vec3 hi = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(hi, lo, lessThanEqual(color, vec3(0.04045)));
+}
+
+vec3 sample_lut(sampler3D lut, vec3 color) {
+    // remap so 0 and 1 land on the centers of the edge texels
+    float size = float(textureSize(lut, 0).x);
+    vec3 uvw = color * ((size - 1.0) / size) + 0.5 / size;
+    return texture(lut, uvw).rgb;
+}
+
+// LUTs are authored against display-encoded values, so grade in sRGB space
+vec3 color_grade(vec3 color) {
+    vec3 encoded = linear_to_srgb(saturate(color));
+    vec3 graded_a = sample_lut(gradingLutA, encoded);
+    vec3 graded_b = sample_lut(gradingLutB, encoded);
+    return srgb_to_linear(saturate(mix(graded_a, graded_b, constants.lut_blend)));
+}
 
 void main() {
     // pipeline luminance to absolute luminance
     vec3 diffuse = subpassLoad(inputDiffuse).rgb * INTERNAL_HDR_DIV;
     vec3 specular = subpassLoad(inputSpecular).rgb * INTERNAL_HDR_DIV;
-    vec3 hdrColor = diffuse + specular;
+    // anti-aliased diffuse + specular from the TAA resolve
+    vec3 hdrColor = texelFetch(resolvedColor, ivec2(gl_FragCoord.xy), 0).rgb * INTERNAL_HDR_DIV;
     scene_color = vec4(hdrColor, 1.0);
 
-    float fragment_luma = dot(diffuse, LUMA_COMPONENTS);
-    // uint conversion offset
-    fragment_luma *= 1000.0;
-    luma_out = uint(fragment_luma);
+    luma_out = dot(diffuse, LUMA_COMPONENTS);
 
     vec2 center = vec2(constants.screen_dimensions[0] / 2, constants.screen_dimensions[1] / 2);
     vec2 distance = abs(gl_FragCoord.xy - center) / center;
     float vignette_amount = smoothstep(0.0, 1.0, length(distance * 0.707));
     float vignette = 1.0 - (vignette_amount * constants.vignette_opacity);
 
-    vec3 tonemapped = hdrColor * 1.0 *constants.exposure_adjustment * vignette;
+    vec3 exposed = hdrColor * constants.exposure * vignette;
+    vec3 tonemapped = tonemap(constants.tonemap_operator, exposed, constants.white_point);
+    if (constants.color_grading_enabled != 0) {
+        tonemapped = color_grade(tonemapped);
+    }
+    // linear output, the sRGB swapchain format handles encoding
     swapchain_out = vec4(tonemapped, 1.0);
 
     if (constants.debug_vis_mode == DEBUG_VISUALIZE_POSITION_BUFFER) {
@@ -79,6 +120,14 @@ void main() {
         vec3 color = (tonemapped * 0.333) + (vec3(occlusion_normalized) * 0.666);
         swapchain_out = vec4(vec3(occlusion_normalized), 1.0);
     }
+    else if (constants.debug_vis_mode == DEBUG_VISUALIZE_VELOCITY_BUFFER) {
+        vec2 velocity = texelFetch(velocityBuffer, ivec2(gl_FragCoord.xy), 0).rg;
+        swapchain_out = vec4(abs(velocity) * 50.0, 0.0, 1.0);
+    }
+    else if (constants.debug_vis_mode == DEBUG_VISUALIZE_AMBIENT_OCCLUSION) {
+        float ao = texelFetch(aoBuffer, ivec2(gl_FragCoord.xy), 0).r;
+        swapchain_out = vec4(vec3(ao), 1.0);
+    }
     else if (constants.debug_vis_mode == DEBUG_VISUALIZE_NO_POST_PROCESSING) {
         // passthrough
         swapchain_out = vec4(hdrColor / INTERNAL_HDR_DIV, 1.0);
@@ -0,0 +1,235 @@
+//! Offscreen and split-screen views.
+//!
+//! A view draws the scene from its own camera into a color target, which is registered in the
+//! [TextureRegistry](::registry::TextureRegistry) under the view's name. Materials that use that name as
+//! their albedo map show what the view sees, e.g. for security camera monitors, mirrors and minimaps.
+//! Views are drawn before the main view. A view can't sample its own target while drawing into it, so it
+//! draws meshes that show its own target with their default textures.
+//!
+//! ```ignore
+//! let view = renderer.add_view("monitor", [512, 512]);
+//! view.camera = Camera::perspective(Deg(60.0));
+//! view.transform = security_camera_transform;
+//! // ...
+//! mesh.materials[0].albedo_map_name = String::from("monitor");
+//! ```
+//!
+//! Views with a `viewport` are sized to a region of the window and composited into it instead of the
+//! main view, for split-screen. Each one has its own camera, exposure and debug visualization. Only
+//! screen text is drawn in split-screen, world-space text is skipped.
+//!
+//! ```ignore
+//! for (i, rect) in ViewportRect::split_screen(2).into_iter().enumerate() {
+//!     renderer.add_viewport(&format!("player{}", i), rect);
+//! }
+//! renderer.view_mut("player1").unwrap().info.debug_visualize_setting = DEBUG_VISUALIZE_NORMAL_BUFFER;
+//! ```
+
+
+use std::sync::Arc;
+
+use cgmath::{Matrix4, SquareMatrix};
+use vulkano::format::B8G8R8A8Srgb;
+use vulkano::image::{AttachmentImage, ImageUsage, ImageViewAccess};
+use toolbox::Transform;
+use parking_lot::Mutex;
+
+use crate::camera::Camera;
+use crate::compute::HistogramCompute;
+use crate::pipeline::{RenderPipelineAbstract, DeferredShadingRenderPipeline, DeferredLightingRenderPipeline, LinesRenderPipeline, PostProcessRenderPipeline, TemporalAARenderPipeline};
+use crate::renderer::{RenderInfo, RendererAttachments, TonemappingInfo, OcclusionCullingInfo, recreate_attachments, update_exposure, DEBUG_VISUALIZE_DISABLED};
+
+
+lazy_static! {
+    /// Transfer source, so split-screen views can be blitted into the swapchain image.
+    static ref VIEW_TARGET_USAGE: ImageUsage = ImageUsage {
+        color_attachment: true,
+        sampled: true,
+        transfer_source: true,
+        ..ImageUsage::none()
+    };
+}
+
+
+/// Region of the window, in normalized coordinates from the top left corner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+impl ViewportRect {
+    pub fn full() -> Self {
+        Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0 }
+    }
+
+    /// Standard split-screen layout for 1 to 4 players: 2 players are stacked, 3 players share
+    /// the top half with the first player taking it whole, and 4 players get a quarter each.
+    pub fn split_screen(players: usize) -> Vec<Self> {
+        let rect = |x, y, width, height| Self { x, y, width, height };
+        match players {
+            0 => Vec::new(),
+            1 => vec![Self::full()],
+            2 => vec![rect(0.0, 0.0, 1.0, 0.5), rect(0.0, 0.5, 1.0, 0.5)],
+            3 => vec![rect(0.0, 0.0, 1.0, 0.5), rect(0.0, 0.5, 0.5, 0.5), rect(0.5, 0.5, 0.5, 0.5)],
+            _ => vec![rect(0.0, 0.0, 0.5, 0.5), rect(0.5, 0.0, 0.5, 0.5), rect(0.0, 0.5, 0.5, 0.5), rect(0.5, 0.5, 0.5, 0.5)],
+        }
+    }
+
+    /// Top left corner and size in pixels, in a window of the given dimensions. Adjacent rects don't overlap or leave gaps.
+    pub fn pixels(&self, dimensions: [u32; 2]) -> ([u32; 2], [u32; 2]) {
+        let edge = |t: f32, size: u32| ((t.max(0.0).min(1.0) * size as f32).round() as u32);
+        let min = [edge(self.x, dimensions[0]), edge(self.y, dimensions[1])];
+        let max = [edge(self.x + self.width, dimensions[0]), edge(self.y + self.height, dimensions[1])];
+        (min, [max[0].saturating_sub(min[0]), max[1].saturating_sub(min[1])])
+    }
+}
+
+
+/// A camera that renders into an offscreen texture.
+pub struct RenderView {
+    /// Name the color target is registered with in the texture registry.
+    name: String,
+    pub camera: Camera,
+    pub transform: Transform,
+    /// Disabled views aren't drawn, and their target keeps the last frame they drew.
+    pub enabled: bool,
+    /// Region of the window the view is composited into, for split-screen. `None` for views that are only
+    /// drawn offscreen. While any enabled view has a viewport, the main view isn't drawn.
+    pub viewport: Option<ViewportRect>,
+    /// Per-view render state. Settings shared by all views (TAA, AO, color grading...) are copied from the
+    /// main view every frame, but tonemapping, exposure and `debug_visualize_setting` are the view's own.
+    pub info: RenderInfo,
+    target: Arc<AttachmentImage<B8G8R8A8Srgb>>,
+    /// Deferred shading, lighting, TAA, post-processing and lines, drawing into `target`.
+    pipelines: Vec<Box<dyn RenderPipelineAbstract>>,
+    /// False until the first histogram result has been metered.
+    exposure_initialized: bool,
+}
+
+
+impl RenderView {
+    /// Creates a view with its own attachments and color target, and registers the target in the texture registry.
+    pub(crate) fn new(name: &str, dimensions: [u32; 2], main: &RenderInfo) -> RenderView {
+        let device = main.device.clone();
+        let info = RenderInfo {
+            image_num: 0,
+            dimensions,
+            camera_transform: Transform::identity(),
+            view_mat: Matrix4::identity(),
+            proj_mat: Matrix4::identity(),
+            unjittered_proj_mat: Matrix4::identity(),
+            prev_view_mat: Matrix4::identity(),
+            prev_proj_mat: Matrix4::identity(),
+            jitter: [0.0, 0.0],
+            camera: Camera::new(),
+            tonemapping_info: TonemappingInfo::default(),
+            histogram_compute: Arc::new(Mutex::new(HistogramCompute::new(device.clone()))),
+            attachments: recreate_attachments(device.clone(), dimensions, main.attachments.occlusion.clone()),
+            // the Hi-Z pyramid is built from the main camera
+            occlusion_culling_info: OcclusionCullingInfo { enabled: false },
+            debug_visualize_setting: DEBUG_VISUALIZE_DISABLED,
+            view_name: Some(name.to_string()),
+            ..main.clone()
+        };
+
+        let target = AttachmentImage::with_usage(device.clone(), dimensions, B8G8R8A8Srgb, VIEW_TARGET_USAGE.clone()).unwrap();
+        let targets = [target.clone() as Arc<dyn ImageViewAccess + Send + Sync>];
+
+        let mut pipelines: Vec<Box<dyn RenderPipelineAbstract>> = vec![
+            Box::new(DeferredShadingRenderPipeline::new(&info)),
+            Box::new(DeferredLightingRenderPipeline::new(&info)),
+            Box::new(TemporalAARenderPipeline::new(&info)),
+            Box::new(PostProcessRenderPipeline::new(&info)),
+            Box::new(LinesRenderPipeline::new(&info)),
+        ];
+        for p in pipelines.iter_mut() {
+            p.recreate_framebuffers_if_none(&targets, &info);
+        }
+        info.tex_registry.insert_render_target(name, target.clone());
+
+        RenderView {
+            name: name.to_string(),
+            camera: Camera::new(),
+            transform: Transform::identity(),
+            enabled: true,
+            viewport: None,
+            info,
+            target,
+            pipelines,
+            exposure_initialized: false,
+        }
+    }
+
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+
+    /// Color target the view draws into.
+    pub fn target(&self) -> Arc<AttachmentImage<B8G8R8A8Srgb>> {
+        self.target.clone()
+    }
+
+
+    /// Recreates the view's attachments and color target at new dimensions, keeping its camera and exposure.
+    pub(crate) fn resize(&mut self, dimensions: [u32; 2]) {
+        let device = self.info.device.clone();
+        self.info.dimensions = dimensions;
+        self.info.attachments = recreate_attachments(device.clone(), dimensions, self.info.attachments.occlusion.clone());
+        self.target = AttachmentImage::with_usage(device, dimensions, B8G8R8A8Srgb, VIEW_TARGET_USAGE.clone()).unwrap();
+        self.info.tex_registry.insert_render_target(&self.name, self.target.clone());
+
+        let targets = [self.target.clone() as Arc<dyn ImageViewAccess + Send + Sync>];
+        for p in self.pipelines.iter_mut() {
+            p.remove_framebuffers();
+            p.recreate_framebuffers_if_none(&targets, &self.info);
+        }
+    }
+
+
+    /// Copies the shared state of the main view, and updates the view's matrices and exposure for a new frame.
+    pub(crate) fn prepare(&mut self, main: &RenderInfo, dt: f32) {
+        let own = &self.info;
+        self.info = RenderInfo {
+            image_num: 0,
+            dimensions: own.dimensions,
+            camera_transform: own.camera_transform.clone(),
+            view_mat: own.view_mat,
+            proj_mat: own.proj_mat,
+            unjittered_proj_mat: own.unjittered_proj_mat,
+            prev_view_mat: own.prev_view_mat,
+            prev_proj_mat: own.prev_proj_mat,
+            jitter: own.jitter,
+            camera: own.camera.clone(),
+            tonemapping_info: own.tonemapping_info.clone(),
+            histogram_compute: own.histogram_compute.clone(),
+            attachments: RendererAttachments {
+                // recreated along with the occlusion pass
+                occlusion: main.attachments.occlusion.clone(),
+                ..own.attachments.clone()
+            },
+            occlusion_culling_info: own.occlusion_culling_info.clone(),
+            debug_visualize_setting: own.debug_visualize_setting,
+            view_name: own.view_name.clone(),
+            ..main.clone()
+        };
+        self.info.update_view(&self.camera, &self.transform);
+        update_exposure(&mut self.info, &mut self.exposure_initialized, dt);
+    }
+
+
+    /// Pipelines to run for the view, in submission order.
+    pub(crate) fn pipelines_mut(&mut self) -> (&mut Vec<Box<dyn RenderPipelineAbstract>>, &RenderInfo) {
+        (&mut self.pipelines, &self.info)
+    }
+}
+
+
+impl Drop for RenderView {
+    fn drop(&mut self) {
+        self.info.tex_registry.remove_render_target(&self.name);
+    }
+}